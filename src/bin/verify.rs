@@ -0,0 +1,60 @@
+// Runs replay files through the game without a window and prints how each game ended:
+//
+//   verify <replay>...
+//
+// One line per file, so results can be diffed against a known good run. A game
+// that ends differently from how it was recorded is a failure.
+
+use std::process;
+
+use tetris::game::TICKS_PER_SECOND;
+use tetris::replay::{Ending, Replay};
+use tetris::replay_viewer::ReplayViewer;
+
+// Ticks to keep going after the last input before giving up on the game ending
+const MAX_IDLE_TICKS: u64 = 10 * 60 * TICKS_PER_SECOND as u64;
+
+fn main() {
+	let paths: Vec<String> = std::env::args().skip(1).collect();
+	if paths.is_empty() {
+		eprintln!("usage: verify <replay>...");
+		process::exit(2);
+	}
+
+	let mut failed = false;
+	for path in paths {
+		let replay = match Replay::load(&path) {
+			Ok(replay) => replay,
+			Err(e) => {
+				println!("{}: error: {}", path, e);
+				failed = true;
+				continue;
+			},
+		};
+		let last_input = replay.inputs.last().map(|input| input.tick).unwrap_or(0);
+		let recorded = replay.ending;
+		// A quit game stops at its last input, any other one runs until it ends
+		let last_tick = if recorded == Some(Ending::Quit) { last_input } else { last_input + MAX_IDLE_TICKS };
+		let mut viewer = ReplayViewer::new(replay);
+		let mut game = viewer.start();
+		while !game.over && game.tick <= last_tick {
+			viewer.step(&mut game);
+		}
+		let ending = if game.won { Ending::Finished } else if game.over { Ending::ToppedOut } else { Ending::Quit };
+		// Files that don't record the ending are expected to end
+		let (end, matches) = match recorded {
+			Some(recorded) if recorded == ending => (String::from(ending.name()), true),
+			Some(recorded) => (format!("{}, recorded as {}", ending.name(), recorded.name()), false),
+			None if ending == Ending::Quit => (String::from("did not end"), false),
+			None => (String::from(ending.name()), true),
+		};
+		if !matches {
+			failed = true;
+		}
+		println!("{}: {} {} score {} lines {} level {} pieces {} ticks {} board {:016x}",
+			path, game.mode.name(), end, game.score, game.lines, game.level, game.pieces, game.tick, game.board.hash());
+	}
+	if failed {
+		process::exit(1);
+	}
+}
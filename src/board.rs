@@ -0,0 +1,189 @@
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+
+use crate::tetromino::Kind;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Cell {
+	Empty,
+	Garbage,
+	Block(Kind), // locked piece of this kind
+}
+
+impl Cell {
+	// One byte per cell for files and hashes
+	pub fn code(self) -> u8 {
+		match self {
+			Cell::Empty => 0,
+			Cell::Garbage => 1,
+			Cell::Block(kind) => 2 + Kind::ALL.iter().position(|k| *k == kind).unwrap() as u8,
+		}
+	}
+
+	pub fn from_code(code: u8) -> Option<Cell> {
+		match code {
+			0 => Some(Cell::Empty),
+			1 => Some(Cell::Garbage),
+			code => Kind::ALL.get(code as usize - 2).map(|kind| Cell::Block(*kind)),
+		}
+	}
+}
+
+// Playfield. Row 0 is the top of the hidden buffer, the visible matrix
+// starts at row `buffer` and ends at the floor. Every cell also keeps the
+// tick it was last set on, so modes can hide blocks as they age.
+
+#[derive(Clone)]
+pub struct Board {
+	pub width: usize,
+	pub height: usize, // visible rows
+	pub buffer: usize, // hidden rows above the visible ones
+	pub tick: u64, // stamped on cells as they are set
+	cells: Vec<Cell>,
+	set_at: Vec<u64>,
+}
+
+// Largest width, height or buffer, boards read from a file or the network are checked against it
+pub const MAX_SIZE: usize = 1000;
+
+impl Board {
+	pub fn new(width: usize, height: usize, buffer: usize) -> Self {
+		Board {
+			width,
+			height,
+			buffer,
+			tick: 0,
+			cells: vec![Cell::Empty; width * (height + buffer)],
+			set_at: vec![0; width * (height + buffer)],
+		}
+	}
+
+	// Standard 10x20 matrix with a 20 row buffer, overridable with --width, --height and --buffer
+	pub fn from_args() -> Self {
+		let arg = |name: &str, default: usize| crate::arg_value(name).and_then(|v| v.parse().ok()).unwrap_or(default);
+		Board::new(arg("--width", 10).clamp(4, MAX_SIZE), arg("--height", 20).clamp(4, MAX_SIZE), arg("--buffer", 20).clamp(2, MAX_SIZE))
+	}
+
+	pub fn rows(&self) -> usize {
+		self.height + self.buffer
+	}
+
+	pub fn get(&self, x: usize, y: usize) -> Cell {
+		self.cells[y * self.width + x]
+	}
+
+	// Cell at signed coordinates, None outside of the board
+	pub fn get_checked(&self, x: i32, y: i32) -> Option<Cell> {
+		if x < 0 || y < 0 || x as usize >= self.width || y as usize >= self.rows() {
+			return None
+		}
+		Some(self.get(x as usize, y as usize))
+	}
+
+	// Walls, floor and the space above the buffer count as occupied
+	pub fn is_free(&self, x: i32, y: i32) -> bool {
+		self.get_checked(x, y) == Some(Cell::Empty)
+	}
+
+	pub fn is_visible(&self, y: i32) -> bool {
+		y >= self.buffer as i32 && y < self.rows() as i32
+	}
+
+	// Row a piece's lowest block is spawned on, row 21 counting from the floor
+	pub fn spawn_row(&self) -> i32 {
+		self.buffer as i32 - 1
+	}
+
+	pub fn set(&mut self, x: usize, y: usize, value: Cell) {
+		self.cells[y * self.width + x] = value;
+		self.set_at[y * self.width + x] = self.tick;
+	}
+
+	// Tick the cell was set on
+	pub fn set_at(&self, x: usize, y: usize) -> u64 {
+		self.set_at[y * self.width + x]
+	}
+
+	// Moves a cell down its column keeping the tick it was set on
+	pub fn copy_down(&mut self, x: usize, from: usize, to: usize) {
+		self.cells[to * self.width + x] = self.cells[from * self.width + x];
+		self.set_at[to * self.width + x] = self.set_at[from * self.width + x];
+	}
+
+	pub fn clear(&mut self) {
+		for cell in self.cells.iter_mut() {
+			*cell = Cell::Empty;
+		}
+		for tick in self.set_at.iter_mut() {
+			*tick = 0;
+		}
+	}
+
+	pub fn row_filled(&self, y: usize) -> bool {
+		(0..self.width).all(|x| self.get(x, y) != Cell::Empty)
+	}
+
+	// Moves every row up, the bottom `count` rows are left empty.
+	// Returns true if blocks were pushed off the top of the buffer.
+	pub fn push_up(&mut self, count: usize) -> bool {
+		let count = count.min(self.rows());
+		let overflow = self.cells[..count * self.width].iter().any(|cell| *cell != Cell::Empty);
+		self.cells.drain(..count * self.width);
+		self.cells.resize(self.width * self.rows(), Cell::Empty);
+		self.set_at.drain(..count * self.width);
+		self.set_at.resize(self.width * self.rows(), self.tick);
+		overflow
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.cells.iter().all(|cell| *cell == Cell::Empty)
+	}
+
+	pub fn garbage_rows(&self) -> usize {
+		(0..self.rows()).filter(|y| (0..self.width).any(|x| self.get(x, *y) == Cell::Garbage)).count()
+	}
+
+	// Snapshot for spectators: width, height and buffer as u16, then one byte per cell from the top
+	pub fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+		for size in [self.width, self.height, self.buffer] {
+			let size = u16::try_from(size).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "board too big"))?;
+			w.write_all(&size.to_le_bytes())?;
+		}
+		let codes: Vec<u8> = self.cells.iter().map(|cell| cell.code()).collect();
+		w.write_all(&codes)
+	}
+
+	pub fn read<R: Read>(r: &mut R) -> io::Result<Board> {
+		let mut size = [0; 6];
+		r.read_exact(&mut size)?;
+		let size_at = |i: usize| u16::from_le_bytes([size[i], size[i + 1]]) as usize;
+		let (width, height, buffer) = (size_at(0), size_at(2), size_at(4));
+		check_size(width, height, buffer)?;
+		let mut board = Board::new(width, height, buffer);
+		let mut codes = vec![0; board.cells.len()];
+		r.read_exact(&mut codes)?;
+		for (cell, code) in board.cells.iter_mut().zip(codes) {
+			*cell = Cell::from_code(code).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "bad cell"))?;
+		}
+		Ok(board)
+	}
+
+	// FNV-1a over the sizes as u16 and every cell, the same on every platform and build
+	pub fn hash(&self) -> u64 {
+		let mut hash: u64 = 0xcbf29ce484222325;
+		let size: Vec<u8> = [self.width, self.height, self.buffer].iter().flat_map(|size| (*size as u16).to_le_bytes()).collect();
+		for byte in size.into_iter().chain(self.cells.iter().map(|cell| cell.code())) {
+			hash ^= byte as u64;
+			hash = hash.wrapping_mul(0x100000001b3);
+		}
+		hash
+	}
+}
+
+// Fails on sizes no board is made with, before anything is allocated for them
+pub fn check_size(width: usize, height: usize, buffer: usize) -> io::Result<()> {
+	if [width, height, buffer].iter().any(|size| *size > MAX_SIZE) {
+		return Err(io::Error::new(io::ErrorKind::InvalidData, "board too big"))
+	}
+	Ok(())
+}
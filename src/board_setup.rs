@@ -0,0 +1,105 @@
+// Starting layouts of the board
+
+use ggez::{Context, GameResult, GameError};
+use ggez::filesystem;
+use rand::Rng;
+use std::io::Read;
+
+use crate::board::{Board, Cell};
+use crate::tetromino::Kind;
+
+#[derive(Clone)]
+pub enum BoardSetup {
+	Empty,
+	Garbage(usize), // B-type: number of garbage rows at the bottom
+	Preset(String, Vec<Vec<Cell>>), // name of a file in resources/presets/ and its rows
+}
+
+impl BoardSetup {
+	pub fn from_args(ctx: &mut Context) -> GameResult<Self> {
+		if let Some(height) = crate::arg_value("--garbage") {
+			let rows = height.parse().map_err(|_| GameError::ConfigError(format!("--garbage: {} is not a number of rows", height)))?;
+			Ok(BoardSetup::Garbage(rows))
+		} else if let Some(name) = crate::arg_value("--preset") {
+			let rows = load_preset(ctx, &name)?;
+			Ok(BoardSetup::Preset(name, rows))
+		} else {
+			Ok(BoardSetup::Empty)
+		}
+	}
+
+	// Fails if the setup doesn't fit on the board, before a game is started with it
+	pub fn check(&self, board: &Board) -> GameResult {
+		match self {
+			BoardSetup::Preset(_, rows) => check_rows(board, rows),
+			_ => Ok(()),
+		}
+	}
+
+	pub fn apply<R: Rng>(&self, board: &mut Board, rng: &mut R) -> GameResult {
+		board.clear();
+		match self {
+			BoardSetup::Empty => (),
+			BoardSetup::Garbage(height) => fill_garbage(board, *height, true, rng),
+			BoardSetup::Preset(_, rows) => place_rows(board, rows)?,
+		}
+		Ok(())
+	}
+}
+
+// Fills the bottom rows with garbage. Messy garbage has a new random hole in
+// every row, clean garbage keeps the hole in one column.
+pub fn fill_garbage<R: Rng>(board: &mut Board, height: usize, messy: bool, rng: &mut R) {
+	let height = height.min(board.height);
+	let mut hole = rng.gen_range(0, board.width);
+	for y in board.rows() - height..board.rows() {
+		if messy && y != board.rows() - height {
+			hole = rng.gen_range(0, board.width);
+		}
+		for x in 0..board.width {
+			board.set(x, y, if x == hole { Cell::Empty } else { Cell::Garbage });
+		}
+	}
+}
+
+pub fn load_preset(ctx: &mut Context, name: &str) -> GameResult<Vec<Vec<Cell>>> {
+	let mut file = filesystem::open(ctx, format!("/presets/{}.txt", name))?;
+	let mut text = String::new();
+	file.read_to_string(&mut text)?;
+	Ok(parse_preset(&text))
+}
+
+// Rows are written top to bottom, '.' is an empty cell, a piece letter (IOTSZJL)
+// is a block of that piece, anything else is garbage. Lines starting with '#' are comments.
+pub fn parse_preset(text: &str) -> Vec<Vec<Cell>> {
+	text.lines()
+		.map(|line| line.trim_end())
+		.filter(|line| !line.is_empty() && !line.starts_with('#'))
+		.map(|line| line.chars().map(|c| match c {
+			'.' => Cell::Empty,
+			c => Kind::from_char(c).map(Cell::Block).unwrap_or(Cell::Garbage),
+		}).collect())
+		.collect()
+}
+
+// Places rows so that the last one lies on the floor
+pub fn place_rows(board: &mut Board, rows: &[Vec<Cell>]) -> GameResult {
+	check_rows(board, rows)?;
+	let top = board.rows() - rows.len();
+	for (i, row) in rows.iter().enumerate() {
+		for (x, cell) in row.iter().enumerate() {
+			board.set(x, top + i, *cell);
+		}
+	}
+	Ok(())
+}
+
+fn check_rows(board: &Board, rows: &[Vec<Cell>]) -> GameResult {
+	if rows.len() > board.height {
+		return Err(GameError::ResourceLoadError(format!("preset has {} rows, board has {}", rows.len(), board.height)));
+	}
+	if let Some(row) = rows.iter().find(|row| row.len() > board.width) {
+		return Err(GameError::ResourceLoadError(format!("preset row has {} cells, board has {}", row.len(), board.width)));
+	}
+	Ok(())
+}
@@ -1,135 +1,331 @@
 // Delayed auto shift
+//
+// Pressing a direction moves the piece once right away. If the direction is
+// held for `delay` ticks the piece shifts again, and then every `repeat` ticks
+// until it is released. A charge that was built up survives a lock: while the
+// new piece makes its first `spawn_window` falls, pressing a direction again
+// resumes shifting at full speed instead of charging from zero.
 
 use ggez::graphics;
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DasState {
+	Idle,                          // nothing held, no charge
+	Charging { ticks_left: i32 },  // direction held, counting down to the first auto shift
+	Repeating { ticks_left: i32 }, // charged, counting down to the next auto shift
+	Stored,                        // released while charged, kept for the next piece's spawn window
+}
+
+// Player's handling settings, in ticks
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Handling {
+	pub delay: i32,
+	pub repeat: i32,
+	pub fallback_keeps_charge: bool,
+}
+
+impl Handling {
+	pub fn new() -> Self {
+		Handling {
+			delay: 14,
+			repeat: 3,
+			fallback_keeps_charge: true,
+		}
+	}
+}
+
+impl Default for Handling {
+	fn default() -> Self {
+		Handling::new()
+	}
+}
+
 pub struct DAS {
-	pub active: bool,
-	pub activating: bool,
-	pub moving: bool,
-	pub moved: bool,
-	pub ticks_to_activate: i32,
-	pub ticks_left_to_activate: i32,
-	pub ticks_to_move: i32,
-	pub ticks_left_to_move: i32,
-	pub need_move: bool,
+	pub state: DasState,
+	pub delay: i32,  // ticks from a press to the first auto shift
+	pub repeat: i32, // ticks between auto shifts
+	pub need_move: bool, // a shift is due, cleared by the caller once the piece has moved
 	pub side: i8,
-	pub new_tetromino: bool,
-	pub new_tetromino_falls: u8,
-	pub new_tetromino_falls_left: i8,
+	pub left_held: bool,
+	pub right_held: bool,
+	// Releasing the active direction while the other one is held keeps the charge
+	pub fallback_keeps_charge: bool,
+	pub spawn_window: u8, // falls of a new piece during which a charge can be reused
+	pub spawn_falls_left: u8,
 }
 
 impl DAS {
 	pub fn new() -> DAS {
+		DAS::with_handling(Handling::new())
+	}
+
+	pub fn with_handling(handling: Handling) -> DAS {
 		DAS {
-			active: false,
-			activating: false,
-			moving: false,
-			moved: false,
-			ticks_to_activate: 37,
-			ticks_left_to_activate:37,
-			ticks_to_move: 8,
-			ticks_left_to_move: 8,
+			state: DasState::Idle,
+			delay: handling.delay,
+			repeat: handling.repeat,
 			need_move: false,
 			side: 0,
-			new_tetromino: false,
-			new_tetromino_falls: 5,
-			new_tetromino_falls_left: 0,
+			left_held: false,
+			right_held: false,
+			fallback_keeps_charge: handling.fallback_keeps_charge,
+			spawn_window: 5,
+			spawn_falls_left: 0,
 		}
 	}
 
 	pub fn tick(&mut self) {
-		if self.activating {
-			self.ticks_left_to_activate -= 1;
-			if self.ticks_left_to_activate < 0 {
-				self.active = true;
-
-				self.activating = false;
-				self.ticks_left_to_move = self.ticks_to_move;
-			}
-		}
-		if self.active && self.moving {
-			// self.need_move = false;
-			self.ticks_left_to_move -= 1;
-			if self.ticks_left_to_move < 0 {
-				self.need_move = true;
-				self.ticks_left_to_move = self.ticks_to_move;
-			}
+		match self.state {
+			DasState::Charging { ticks_left } | DasState::Repeating { ticks_left } => {
+				if ticks_left <= 1 {
+					self.need_move = true;
+					self.state = DasState::Repeating { ticks_left: self.repeat };
+				} else if let DasState::Charging { .. } = self.state {
+					self.state = DasState::Charging { ticks_left: ticks_left - 1 };
+				} else {
+					self.state = DasState::Repeating { ticks_left: ticks_left - 1 };
+				}
+			},
+			_ => (),
 		}
 	}
 
+	// The current piece fell by one row
 	pub fn fall(&mut self) {
-		if self.new_tetromino {
-			self.new_tetromino_falls_left -= 1;
-			if self.new_tetromino_falls_left <= 0 {
-				self.new_tetromino_falls_left = 0;
-				self.new_tetromino = false;
-				if !self.moving {
-					self.deactivate();
-				}
-
+		if self.spawn_falls_left > 0 {
+			self.spawn_falls_left -= 1;
+			if self.spawn_falls_left == 0 && self.state == DasState::Stored {
+				self.state = DasState::Idle;
 			}
 		}
 	}
 
-	pub fn start_moving(&mut self, side: i8) {
-		if (self.side != side && !self.new_tetromino) || (!self.new_tetromino && !self.moving)  {
-			self.deactivate();
+	// A piece locked and the next one spawned
+	pub fn new_tetromino(&mut self) {
+		self.spawn_falls_left = self.spawn_window;
+	}
+
+	// Direction key went down, the last pressed direction wins
+	pub fn press(&mut self, side: i8) {
+		if self.held(side) {
+			return
 		}
+		self.set_held(side, true);
+		self.need_move = true;
 		self.side = side;
-		if !self.active {
-			self.activating = true;
-		}
-		self.moving = true;
-		self.new_tetromino = false;
+		let in_spawn_window = self.spawn_falls_left > 0;
+		self.spawn_falls_left = 0;
+		// Only a stored charge carries over, switching from a held direction starts a fresh one
+		self.state = match self.state {
+			DasState::Stored if in_spawn_window => DasState::Repeating { ticks_left: self.repeat },
+			_ => DasState::Charging { ticks_left: self.delay },
+		};
 	}
 
-	pub fn stop_moving(&mut self) {
-		self.activating = false;
-		self.ticks_left_to_activate = self.ticks_to_activate;
-		self.ticks_left_to_move = self.ticks_to_move;
-		self.moving = false;
-		self.need_move = false;
-		// if self.moved {
-		// 	self.active = false;
-		// 	self.ticks_left_to_move = self.ticks_left_to_move;
-		// 	self.ticks_left_to_activate = self.ticks_to_activate
-		// }
+	// Direction key went up, movement falls back to the other direction if it is still held
+	pub fn release(&mut self, side: i8) {
+		if !self.held(side) {
+			return
+		}
+		self.set_held(side, false);
+		if self.side != side || !self.moving() {
+			return
+		}
+		if self.held(-side) {
+			self.side = -side;
+			self.need_move = true;
+			if !self.fallback_keeps_charge {
+				self.state = DasState::Charging { ticks_left: self.delay };
+			}
+		} else {
+			self.need_move = false;
+			self.state = match self.state {
+				DasState::Repeating { .. } => DasState::Stored,
+				_ => DasState::Idle,
+			};
+		}
 	}
 
-	pub fn deactivate(&mut self) {
-		self.active = false;
-		self.ticks_left_to_activate = self.ticks_to_activate;
-		self.ticks_left_to_move = self.ticks_left_to_move;
+	pub fn moving(&self) -> bool {
+		matches!(self.state, DasState::Charging { .. } | DasState::Repeating { .. })
+	}
 
+	pub fn held(&self, side: i8) -> bool {
+		if side < 0 { self.left_held } else { self.right_held }
 	}
 
-	pub fn new_tetromino(&mut self) {
-		self.new_tetromino = true;
-		self.new_tetromino_falls_left = self.new_tetromino_falls as i8;
+	fn set_held(&mut self, side: i8, held: bool) {
+		if side < 0 {
+			self.left_held = held;
+		} else {
+			self.right_held = held;
+		}
 	}
+}
 
+impl Default for DAS {
+	fn default() -> Self {
+		DAS::new()
+	}
 }
 
-pub struct DAS_DEBUG {
+pub struct DasDebug {
 
 }
 
-impl DAS_DEBUG {
+impl DasDebug {
 	pub fn debug_mesh(ctx: &mut ggez::Context, das: &DAS, cellsize: f32) -> ggez::graphics::Mesh {
-		let mut red = 355 - das.ticks_left_to_activate as i32;
-		red = 200;
-		// if red < 100 {
-		// 	red = 100;
-		// }
+		let red = match das.state {
+			DasState::Charging { .. } => 120,
+			DasState::Repeating { .. } | DasState::Stored => 200,
+			DasState::Idle => 0,
+		};
 		let mut green = 0;
-		if das.new_tetromino {
+		if das.spawn_falls_left > 0 {
 			green = 255;
 		}
-		let mut blue = 0;
-		// if das.activating {
-		// 	blue = 255;
-		// }
+		let blue = if das.need_move { 255 } else { 0 };
 
 		graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::fill(), graphics::Rect{x:0.,y:0.,w:cellsize,h:cellsize}, graphics::Color::from((red as u8,green as u8,blue as u8))).unwrap()
 	}
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	enum Step {
+		Press(i8),
+		Release(i8),
+		Ticks(usize),
+		Fall(usize),
+		Lock,
+	}
+
+	// Feeds the steps into a DAS and returns (tick, side) for every shift it asked for.
+	// Shifts are assumed to succeed, a shift due on a press is reported with the current tick.
+	fn run(das: &mut DAS, steps: &[Step]) -> Vec<(usize, i8)> {
+		let mut moves = Vec::new();
+		let mut tick = 0;
+		for step in steps {
+			match step {
+				Step::Press(side) => das.press(*side),
+				Step::Release(side) => das.release(*side),
+				Step::Fall(n) => for _ in 0..*n { das.fall() },
+				Step::Lock => das.new_tetromino(),
+				Step::Ticks(n) => {
+					for _ in 0..*n {
+						tick += 1;
+						das.tick();
+						if das.need_move {
+							moves.push((tick, das.side));
+							das.need_move = false;
+						}
+					}
+					continue;
+				},
+			}
+			if das.need_move {
+				moves.push((tick, das.side));
+				das.need_move = false;
+			}
+		}
+		moves
+	}
+
+	fn das() -> DAS {
+		let mut das = DAS::new();
+		das.delay = 10;
+		das.repeat = 3;
+		das.spawn_window = 2;
+		das
+	}
+
+	#[test]
+	fn tap_moves_once() {
+		let mut das = das();
+		let moves = run(&mut das, &[Step::Press(-1), Step::Ticks(5), Step::Release(-1), Step::Ticks(30)]);
+		assert_eq!(moves, vec![(0, -1)]);
+		assert_eq!(das.state, DasState::Idle);
+	}
+
+	#[test]
+	fn hold_shifts_after_delay_then_every_repeat() {
+		let mut das = das();
+		let moves = run(&mut das, &[Step::Press(1), Step::Ticks(20)]);
+		assert_eq!(moves, vec![(0, 1), (10, 1), (13, 1), (16, 1), (19, 1)]);
+	}
+
+	#[test]
+	fn last_pressed_direction_wins_with_fresh_charge() {
+		let mut das = das();
+		let moves = run(&mut das, &[Step::Press(-1), Step::Ticks(12), Step::Press(1), Step::Ticks(10)]);
+		assert_eq!(moves, vec![(0, -1), (10, -1), (12, 1), (22, 1)]);
+	}
+
+	#[test]
+	fn fallback_keeps_charge() {
+		let mut das = das();
+		let moves = run(&mut das, &[Step::Press(-1), Step::Press(1), Step::Ticks(10), Step::Release(1), Step::Ticks(6)]);
+		assert_eq!(moves, vec![(0, -1), (0, 1), (10, 1), (10, -1), (13, -1), (16, -1)]);
+	}
+
+	#[test]
+	fn fallback_with_fresh_charge() {
+		let mut das = das();
+		das.fallback_keeps_charge = false;
+		let moves = run(&mut das, &[Step::Press(-1), Step::Press(1), Step::Ticks(10), Step::Release(1), Step::Ticks(10)]);
+		assert_eq!(moves, vec![(0, -1), (0, 1), (10, 1), (10, -1), (20, -1)]);
+	}
+
+	#[test]
+	fn releasing_inactive_direction_changes_nothing() {
+		let mut das = das();
+		let moves = run(&mut das, &[Step::Press(-1), Step::Press(1), Step::Ticks(4), Step::Release(-1), Step::Ticks(6)]);
+		assert_eq!(moves, vec![(0, -1), (0, 1), (10, 1)]);
+		assert!(!das.left_held);
+	}
+
+	#[test]
+	fn repeated_press_is_ignored() {
+		let mut das = das();
+		let moves = run(&mut das, &[Step::Press(1), Step::Ticks(5), Step::Press(1), Step::Ticks(5)]);
+		assert_eq!(moves, vec![(0, 1), (10, 1)]);
+	}
+
+	#[test]
+	fn held_charge_carries_into_next_piece() {
+		let mut das = das();
+		let moves = run(&mut das, &[Step::Press(1), Step::Ticks(10), Step::Lock, Step::Fall(5), Step::Ticks(3)]);
+		assert_eq!(moves, vec![(0, 1), (10, 1), (13, 1)]);
+	}
+
+	#[test]
+	fn stored_charge_is_reused_in_spawn_window() {
+		let mut das = das();
+		let moves = run(&mut das, &[Step::Press(1), Step::Ticks(10), Step::Release(1), Step::Lock, Step::Fall(1), Step::Press(-1), Step::Ticks(3)]);
+		assert_eq!(moves, vec![(0, 1), (10, 1), (10, -1), (13, -1)]);
+	}
+
+	#[test]
+	fn switching_direction_in_spawn_window_starts_a_fresh_charge() {
+		let mut das = das();
+		let moves = run(&mut das, &[Step::Press(1), Step::Ticks(10), Step::Lock, Step::Fall(1), Step::Press(-1), Step::Ticks(10)]);
+		assert_eq!(moves, vec![(0, 1), (10, 1), (10, -1), (20, -1)]);
+	}
+
+	#[test]
+	fn stored_charge_expires_after_spawn_window() {
+		let mut das = das();
+		let moves = run(&mut das, &[Step::Press(1), Step::Ticks(10), Step::Release(1), Step::Lock, Step::Fall(2), Step::Press(1), Step::Ticks(9)]);
+		assert_eq!(moves, vec![(0, 1), (10, 1), (10, 1)]);
+		assert_eq!(das.state, DasState::Charging { ticks_left: 1 });
+	}
+
+	#[test]
+	fn stored_charge_is_not_reused_without_a_new_piece() {
+		let mut das = das();
+		let moves = run(&mut das, &[Step::Press(1), Step::Ticks(10), Step::Release(1), Step::Press(1), Step::Ticks(9)]);
+		assert_eq!(moves, vec![(0, 1), (10, 1), (10, 1)]);
+	}
+}
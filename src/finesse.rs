@@ -0,0 +1,233 @@
+// Finesse: the fewest inputs that take a piece from its spawn position to
+// where it was placed. Movement is worked out on an empty board of the same
+// size, the way finesse is usually counted, with a held direction (DAS to
+// the wall) as a single input. Hard drop ends every placement and is not counted.
+
+use std::collections::{HashMap, VecDeque};
+
+use crate::board::Board;
+use crate::tetromino::{Tetromino, Kind};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FinesseInput {
+	Left,
+	Right,
+	DasLeft, // held until the piece reaches the wall
+	DasRight,
+	RotateCW,
+	RotateCCW,
+	Rotate180,
+}
+
+impl FinesseInput {
+	const ALL: [FinesseInput; 7] = [
+		FinesseInput::DasLeft, FinesseInput::DasRight, FinesseInput::Left, FinesseInput::Right,
+		FinesseInput::RotateCW, FinesseInput::RotateCCW, FinesseInput::Rotate180,
+	];
+
+	pub fn label(self) -> &'static str {
+		match self {
+			FinesseInput::Left => "left",
+			FinesseInput::Right => "right",
+			FinesseInput::DasLeft => "DAS left",
+			FinesseInput::DasRight => "DAS right",
+			FinesseInput::RotateCW => "CW",
+			FinesseInput::RotateCCW => "CCW",
+			FinesseInput::Rotate180 => "180",
+		}
+	}
+
+	// Applies the input, returns false if the piece didn't move
+	fn apply(self, tetr: &mut Tetromino, board: &Board) -> bool {
+		match self {
+			FinesseInput::Left | FinesseInput::Right => {
+				let dir = if self == FinesseInput::Left { -1 } else { 1 };
+				let moved = tetr.can_move(board, dir);
+				if moved {
+					tetr.pos.x += dir as i32;
+				}
+				moved
+			},
+			FinesseInput::DasLeft | FinesseInput::DasRight => {
+				let dir = if self == FinesseInput::DasLeft { -1 } else { 1 };
+				let mut moved = false;
+				while tetr.can_move(board, dir) {
+					tetr.pos.x += dir as i32;
+					moved = true;
+				}
+				moved
+			},
+			FinesseInput::RotateCW => tetr.rotate(board, 1),
+			FinesseInput::RotateCCW => tetr.rotate(board, -1),
+			FinesseInput::Rotate180 => tetr.rotate_180(board),
+		}
+	}
+}
+
+// A piece as it locked, with the inputs the player used to get it there
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Placement {
+	pub kind: Kind,
+	pub rotation: usize,
+	pub x: i32,
+	pub presses: u32, // moves and rotations pressed, auto shift not counted
+	pub soft_dropped: bool, // soft dropped pieces can tuck into places a hard drop can't reach
+}
+
+// Blocks of a piece as columns and rows from its top, the same for every
+// position that lands in the same place when dropped
+fn footprint(tetr: &Tetromino) -> Vec<(i32, i32)> {
+	let mut cells = Vec::new();
+	for y in 0..crate::tetromino_height {
+		for x in 0..crate::tetromino_width {
+			if tetr.blocks[tetr.rotation][y][x] == 1 {
+				cells.push((tetr.pos.x + x as i32, y as i32));
+			}
+		}
+	}
+	let top = cells.iter().map(|(_, y)| *y).min().unwrap_or(0);
+	for cell in cells.iter_mut() {
+		cell.1 -= top;
+	}
+	cells.sort();
+	cells
+}
+
+// Position and rotation of a piece while searching
+type State = (i32, i32, usize);
+
+// Shortest input sequence from spawn to the placement, None if it can't be reached that way
+pub fn optimal(placement: &Placement, width: usize) -> Option<Vec<FinesseInput>> {
+	let board = Board::new(width, 20, 20);
+	let mut target = Tetromino::new(placement.kind);
+	target.rotation = placement.rotation;
+	target.pos.x = placement.x;
+	let target = footprint(&target);
+
+	let mut start = Tetromino::new(placement.kind);
+	start.spawn(&board);
+	// Breadth first over (x, y, rotation), remembering how each state was first reached
+	let key = |t: &Tetromino| -> State { (t.pos.x, t.pos.y, t.rotation) };
+	let mut came_from: HashMap<State, Option<(State, FinesseInput)>> = HashMap::new();
+	came_from.insert(key(&start), None);
+	let mut queue = VecDeque::new();
+	queue.push_back(start);
+	while let Some(tetr) = queue.pop_front() {
+		if footprint(&tetr) == target {
+			let mut inputs = Vec::new();
+			let mut state = key(&tetr);
+			while let Some(Some((previous, input))) = came_from.get(&state) {
+				inputs.push(*input);
+				state = *previous;
+			}
+			inputs.reverse();
+			return Some(inputs)
+		}
+		for input in FinesseInput::ALL.iter() {
+			let mut next = tetr.clone();
+			if input.apply(&mut next, &board) && !came_from.contains_key(&key(&next)) {
+				came_from.insert(key(&next), Some((key(&tetr), *input)));
+				queue.push_back(next);
+			}
+		}
+	}
+	None
+}
+
+// What the trainer shows for the last placement
+pub struct FinesseResult {
+	pub optimal: Vec<FinesseInput>,
+	pub presses: u32,
+}
+
+impl FinesseResult {
+	pub fn is_error(&self) -> bool {
+		self.presses as usize > self.optimal.len()
+	}
+}
+
+// Checks every placement of a session against the optimal inputs
+pub struct FinesseTrainer {
+	pub pieces: u32, // placements that were judged
+	pub errors: u32,
+	pub last: Option<FinesseResult>,
+}
+
+impl FinesseTrainer {
+	pub fn new() -> Self {
+		FinesseTrainer {
+			pieces: 0,
+			errors: 0,
+			last: None,
+		}
+	}
+
+	pub fn placed(&mut self, placement: &Placement, width: usize) {
+		if placement.soft_dropped {
+			self.last = None;
+			return
+		}
+		let result = optimal(placement, width).map(|optimal| FinesseResult { optimal, presses: placement.presses });
+		if let Some(result) = &result {
+			self.pieces += 1;
+			if result.is_error() {
+				self.errors += 1;
+			}
+		}
+		self.last = result;
+	}
+
+	// Side panel lines: the error count and, after a mistake, the inputs that would have done it
+	pub fn overlay(&self) -> Vec<String> {
+		let mut lines = vec![format!("Finesse errors {}/{}", self.errors, self.pieces)];
+		if let Some(last) = self.last.as_ref().filter(|last| last.is_error()) {
+			let inputs: Vec<&str> = last.optimal.iter().map(|input| input.label()).collect();
+			lines.push(format!("{} keys, best {}", last.presses, last.optimal.len()));
+			lines.push(if inputs.is_empty() { String::from("Just drop") } else { inputs.join(", ") });
+		}
+		lines
+	}
+}
+
+impl Default for FinesseTrainer {
+	fn default() -> Self {
+		FinesseTrainer::new()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn placement(kind: Kind, rotation: usize, x: i32) -> Placement {
+		Placement { kind, rotation, x, presses: 0, soft_dropped: false }
+	}
+
+	#[test]
+	fn spawn_position_needs_no_inputs() {
+		assert_eq!(optimal(&placement(Kind::T, 0, 4), 10), Some(vec![]));
+	}
+
+	#[test]
+	fn walls_take_das() {
+		assert_eq!(optimal(&placement(Kind::O, 0, 7), 10), Some(vec![FinesseInput::DasRight]));
+		// Vertical I against the left wall, its blocks are in the second column
+		assert_eq!(optimal(&placement(Kind::I, 1, -1), 10).map(|inputs| inputs.len()), Some(2));
+		assert_eq!(optimal(&placement(Kind::T, 0, 3), 10), Some(vec![FinesseInput::Left]));
+	}
+
+	#[test]
+	fn mirrored_rotations_count_as_the_same_placement() {
+		// I pieces look the same in rotations 0 and 2
+		assert_eq!(optimal(&placement(Kind::I, 2, 4), 10), Some(vec![]));
+	}
+
+	#[test]
+	fn trainer_counts_extra_presses() {
+		let mut trainer = FinesseTrainer::new();
+		trainer.placed(&Placement { presses: 3, ..placement(Kind::O, 0, 7) }, 10);
+		trainer.placed(&Placement { presses: 1, ..placement(Kind::O, 0, 7) }, 10);
+		trainer.placed(&Placement { presses: 9, soft_dropped: true, ..placement(Kind::O, 0, 7) }, 10);
+		assert_eq!((trainer.errors, trainer.pieces), (1, 2));
+	}
+}
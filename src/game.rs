@@ -0,0 +1,824 @@
+// Game rules and simulation, advanced in fixed ticks and driven by actions.
+// Nothing here needs a ggez Context, so a game can be replayed without a window.
+
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+
+use crate::board::{Board, Cell};
+use crate::board_setup::{BoardSetup, fill_garbage};
+use crate::das::{DAS, Handling};
+use crate::input::GameAction;
+use crate::master::{self, Checkpoint, MAX_LEVEL};
+use crate::mode::{Mode, MarathonGoal, SPLIT_LINES, survival_interval};
+use crate::puzzle::PuzzleGoal;
+use crate::finesse::Placement;
+use crate::tetromino::{Tetromino, Kind};
+
+pub const TICKS_PER_SECOND: u32 = 60;
+// Points for clearing 0 to 4 lines at once, multiplied by the level
+const LINE_SCORES: [u32; 5] = [0, 100, 300, 500, 800];
+// How long the stack's outline shows after a line clear in invisible mode
+const OUTLINE_TICKS: u64 = TICKS_PER_SECOND as u64;
+
+// Things that happened during an action or a tick, for whoever draws or records the game
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GameEvent {
+	Locked { lines: u32 }, // piece placed, with the number of lines it cleared
+	Held,
+	ToppedOut,
+	Finished, // the mode's goal was reached
+	GoalReached, // goal reached in an endless game, play goes on
+	GarbageAdded { rows: u32 },
+}
+
+pub struct Game {
+	pub mode: Mode,
+	pub board: Board,
+	pub tetr: Tetromino,
+	pub next: Kind,
+	pub hold: Option<Kind>,
+	pub hold_used: bool, // hold can be used once per piece
+	pub das: DAS,
+	pub level: u16,
+	pub lines: u16,
+	pub goal_progress: u16, // lines counted towards the current level's goal
+	pub score: u32,
+	pub clears: [u32; 5], // number of placements by lines cleared, 0 to 4
+	pub clear_points: [u32; 5], // points scored by those clears
+	pub drop_points: u32,
+	pub pieces: u32,
+	pub tick: u64,
+	pub over: bool,
+	pub won: bool, // reached the mode's goal, the game is over unless it is an endless marathon
+	pub splits: Vec<u64>, // tick of every SPLIT_LINES lines in sprint
+	pub rise_timer: u64, // ticks until the next garbage row in survival
+	pub rises: u32,
+	pub entry_delay: u32, // ticks until the next piece enters, master only
+	pub lock_timer: u32, // ticks the piece has been resting on the stack
+	pub combo: u32,
+	pub checkpoints: Vec<Checkpoint>, // every 100 levels in master
+	pub last_clear: Option<u64>, // tick of the last line clear
+	pub sequence: Vec<Kind>, // fixed pieces to come after `next`, random ones follow
+	pub piece_limit: Option<u32>, // the game ends after this many pieces
+	pub presses: u32, // key presses that reached the game
+	pub t_spins: u32,
+	pub last_placement: Option<Placement>, // the piece that locked last, for the finesse trainer
+	pub incoming: Vec<u32>, // versus garbage meter: attacks waiting to rise, oldest first
+	pub sent: u32, // rows of garbage attacked with in versus
+	pub events: Vec<GameEvent>,
+	soft_drop: bool,
+	fall_progress: u32, // 1/256ths of a row
+	soft_rows: u32, // soft dropped by the current piece
+	last_rotated: bool, // the piece's last move was a rotation, for T-spins
+	piece_presses: u32, // moves and rotations pressed for the current piece
+	attack: u32, // rows sent since the opponent last took them
+	rng: StdRng,
+}
+
+impl Game {
+	pub fn new(seed: u64, mut board: Board, setup: &BoardSetup, handling: Handling, mode: Mode) -> Game {
+		let mut rng = StdRng::seed_from_u64(seed);
+		if let Err(e) = setup.apply(&mut board, &mut rng) {
+			eprintln!("board setup: {}", e);
+		}
+		if let Mode::Dig { rows, messy } = mode {
+			fill_garbage(&mut board, rows as usize, messy, &mut rng);
+		}
+		let mut tetr = Tetromino::new(Kind::random(&mut rng));
+		tetr.spawn(&board);
+		let next = Kind::random(&mut rng);
+		Game {
+			mode,
+			board,
+			tetr,
+			next,
+			hold: None,
+			hold_used: false,
+			das: DAS::with_handling(handling),
+			level: if mode == Mode::Master { 0 } else { 1 },
+			lines: 0,
+			goal_progress: 0,
+			score: 0,
+			clears: [0; 5],
+			clear_points: [0; 5],
+			drop_points: 0,
+			pieces: 0,
+			tick: 0,
+			over: false,
+			won: false,
+			splits: Vec::new(),
+			rise_timer: survival_interval(0),
+			rises: 0,
+			entry_delay: 0,
+			lock_timer: 0,
+			combo: 1,
+			checkpoints: Vec::new(),
+			last_clear: None,
+			sequence: Vec::new(),
+			piece_limit: None,
+			presses: 0,
+			t_spins: 0,
+			last_placement: None,
+			incoming: Vec::new(),
+			sent: 0,
+			events: Vec::new(),
+			soft_drop: false,
+			fall_progress: 0,
+			soft_rows: 0,
+			last_rotated: false,
+			piece_presses: 0,
+			attack: 0,
+			rng,
+		}
+	}
+
+	// Replaces the random pieces with a fixed sequence, the game ends once they are all placed
+	pub fn set_pieces(&mut self, pieces: &[Kind]) {
+		if pieces.is_empty() {
+			return
+		}
+		self.sequence = pieces[1..].to_vec();
+		self.next = self.draw_kind();
+		self.piece_limit = Some(pieces.len() as u32);
+		self.spawn(pieces[0]);
+	}
+
+	// Whether the next piece is part of the fixed sequence, random pieces past its end are never played
+	pub fn next_shown(&self) -> bool {
+		self.pieces_left().is_none_or(|left| left > 1 + self.hold.is_some() as u32)
+	}
+
+	// Pieces of the fixed sequence not placed yet, the one in play and the held one included
+	fn pieces_left(&self) -> Option<u32> {
+		self.piece_limit.map(|limit| limit.saturating_sub(self.pieces))
+	}
+
+	fn draw_kind(&mut self) -> Kind {
+		if self.sequence.is_empty() {
+			Kind::random(&mut self.rng)
+		} else {
+			self.sequence.remove(0)
+		}
+	}
+
+	// Rows per tick in 1/256ths
+	pub fn gravity(&self) -> u32 {
+		let gravity = self.mode.gravity(self.level);
+		if self.soft_drop {
+			(gravity * 26).max(256)
+		} else {
+			gravity
+		}
+	}
+
+	pub fn tick(&mut self) {
+		if self.over {
+			return
+		}
+		self.tick += 1;
+		self.board.tick = self.tick;
+		if let Mode::Ultra { seconds } = self.mode {
+			if self.tick >= seconds as u64 * TICKS_PER_SECOND as u64 {
+				self.finish();
+				return
+			}
+		}
+
+		if self.mode == Mode::Survival {
+			self.rise_timer -= 1;
+			if self.rise_timer == 0 {
+				self.rises += 1;
+				self.rise_timer = survival_interval(self.rises);
+				self.add_garbage(1, true);
+				if self.over {
+					return
+				}
+			}
+		}
+
+		let timings = self.mode.timings(self.level);
+		if let Some(timings) = timings {
+			self.das.delay = timings.das;
+		}
+		self.das.tick();
+		// DAS keeps charging while the next piece is waiting to enter
+		if self.entry_delay > 0 {
+			self.entry_delay -= 1;
+			if self.entry_delay == 0 {
+				self.next_piece();
+			}
+			return
+		}
+		if self.das.need_move && self.tetr.move_tetromino(&mut self.board, self.das.side) {
+			self.das.need_move = false;
+			self.last_rotated = false;
+		}
+
+		self.fall_progress += self.gravity();
+		while self.fall_progress >= 256 {
+			self.fall_progress -= 256;
+			// With a lock delay a landed piece waits for the lock timer instead
+			if timings.is_some() && !self.tetr.can_fall(&self.board) {
+				self.fall_progress = 0;
+				break;
+			}
+			if self.tetr.fall(&mut self.board) {
+				self.lock_tetromino();
+				break;
+			}
+			self.lock_timer = 0;
+			self.last_rotated = false;
+			if self.soft_drop {
+				self.score += 1;
+				self.drop_points += 1;
+				self.soft_rows += 1;
+			}
+			self.das.fall();
+		}
+
+		if let Some(timings) = timings {
+			if !self.tetr.can_fall(&self.board) {
+				self.lock_timer += 1;
+				// Soft drop locks a landed piece right away
+				if self.lock_timer >= timings.lock || self.soft_drop {
+					self.tetr.place_to_grid(&mut self.board);
+					self.lock_tetromino();
+				}
+			}
+		}
+	}
+
+	pub fn action(&mut self, action: GameAction, pressed: bool) {
+		if self.over {
+			return
+		}
+		// Only direction and soft drop keys are tracked while the next piece waits to enter
+		let held = matches!(action, GameAction::MoveLeft | GameAction::MoveRight | GameAction::SoftDrop);
+		if self.entry_delay > 0 && !held {
+			return
+		}
+		if pressed {
+			self.presses += 1;
+			if let GameAction::MoveLeft | GameAction::MoveRight | GameAction::RotateCW | GameAction::RotateCCW | GameAction::Rotate180 = action {
+				self.piece_presses += 1;
+			}
+		}
+		match (action, pressed) {
+			(GameAction::MoveLeft, true) => self.das.press(-1),
+			(GameAction::MoveLeft, false) => self.das.release(-1),
+			(GameAction::MoveRight, true) => self.das.press(1),
+			(GameAction::MoveRight, false) => self.das.release(1),
+			(GameAction::SoftDrop, pressed) => self.soft_drop = pressed,
+			(GameAction::RotateCCW, true) => self.last_rotated = self.tetr.rotate(&self.board, -1) || self.last_rotated,
+			(GameAction::RotateCW, true) => self.last_rotated = self.tetr.rotate(&self.board, 1) || self.last_rotated,
+			(GameAction::Rotate180, true) => self.last_rotated = self.tetr.rotate_180(&self.board) || self.last_rotated,
+			(GameAction::HardDrop, true) => {
+				let rows = self.tetr.hard_drop(&mut self.board);
+				self.score += 2 * rows as u32;
+				self.drop_points += 2 * rows as u32;
+				self.lock_tetromino();
+			},
+			(GameAction::Hold, true) => self.hold(),
+			_ => (),
+		}
+	}
+
+	// Called after the current piece has been placed to the board
+	fn lock_tetromino(&mut self) {
+		let locked_out = self.tetr.above_visible(&self.board);
+		let t_spin = self.last_rotated && self.tetr.t_corners_blocked(&self.board);
+		self.last_placement = Some(Placement {
+			kind: self.tetr.kind,
+			rotation: self.tetr.rotation,
+			x: self.tetr.pos.x,
+			presses: self.piece_presses,
+			soft_dropped: self.soft_rows > 0,
+		});
+		let lines_before = self.lines;
+		let level = self.level as u32;
+		let rowsinfo = self.check_rows();
+		for row in rowsinfo.iter() {
+			if row.1 {
+				self.clear_row(row.0);
+				self.lower_above(row.0);
+			}
+		}
+
+		let cleared = (self.lines - lines_before) as u32;
+		let clear = cleared.min(4) as usize;
+		self.clears[clear] += 1;
+		self.pieces += 1;
+		self.fall_progress = 0;
+		self.das.new_tetromino();
+		self.events.push(GameEvent::Locked { lines: cleared });
+		if cleared > 0 {
+			self.last_clear = Some(self.tick);
+		}
+		if t_spin {
+			self.t_spins += 1;
+		}
+		if let Mode::Versus(table) = self.mode {
+			let mut attack = table.attack(cleared, t_spin, cleared > 0 && self.board.is_empty());
+			// Attacks cancel the garbage waiting in the meter first
+			while attack > 0 && !self.incoming.is_empty() {
+				let cancelled = attack.min(self.incoming[0]);
+				attack -= cancelled;
+				self.incoming[0] -= cancelled;
+				if self.incoming[0] == 0 {
+					self.incoming.remove(0);
+				}
+			}
+			self.attack += attack;
+			self.sent += attack;
+			// Without a clear the meter empties into the board, each attack with its own hole
+			if cleared == 0 {
+				for rows in std::mem::take(&mut self.incoming) {
+					self.rise_garbage(rows as usize, false);
+				}
+				if self.over {
+					return
+				}
+			}
+		}
+		if self.mode == Mode::Master {
+			self.combo = if cleared > 0 { self.combo + 2 * cleared - 2 } else { 1 };
+			if cleared > 0 {
+				let points = master::clear_score(self.level, cleared, self.soft_rows, self.combo, self.board.is_empty());
+				self.score += points;
+				self.clear_points[clear] += points;
+			}
+			self.advance_level(cleared as u16);
+			if self.level >= MAX_LEVEL {
+				self.finish();
+				return
+			}
+		} else {
+			self.score += LINE_SCORES[clear] * level;
+			self.clear_points[clear] += LINE_SCORES[clear] * level;
+			self.goal_progress += self.mode.awarded_lines(cleared);
+			while self.goal_progress >= self.mode.level_goal(self.level) {
+				self.goal_progress -= self.mode.level_goal(self.level);
+				self.level += 1;
+			}
+		}
+		if let Mode::Dig { .. } = self.mode {
+			if self.board.garbage_rows() == 0 {
+				self.finish();
+				return
+			}
+		}
+		if let Mode::Marathon { goal, endless, .. } = self.mode {
+			let reached = match goal {
+				MarathonGoal::Lines(lines) => self.lines >= lines,
+				MarathonGoal::Level(level) => self.level > level,
+			};
+			if reached && !self.won {
+				if !endless {
+					self.finish();
+					return
+				}
+				self.won = true;
+				self.events.push(GameEvent::GoalReached);
+			}
+		}
+		if let Mode::Puzzle(goal) = self.mode {
+			let solved = match goal {
+				PuzzleGoal::Lines(lines) => self.lines >= lines,
+				PuzzleGoal::PerfectClear => cleared > 0 && self.board.is_empty(),
+				PuzzleGoal::TSpinDouble => t_spin && cleared == 2,
+				PuzzleGoal::Keys { presses, lines } => self.lines >= lines && self.presses <= presses as u32,
+			};
+			if solved {
+				self.finish();
+				return
+			}
+			// Running out of pieces or keys fails the puzzle like a top out
+			let out_of_keys = match goal {
+				PuzzleGoal::Keys { presses, .. } => self.presses >= presses as u32,
+				_ => false,
+			};
+			if out_of_keys || self.piece_limit.is_some_and(|limit| self.pieces >= limit) {
+				self.top_out();
+				return
+			}
+		}
+		if let Mode::Invisible { lines, .. } = self.mode {
+			if self.lines >= lines {
+				self.finish();
+				return
+			}
+		}
+		if let Mode::Sprint { lines } = self.mode {
+			while self.lines >= (self.splits.len() as u16 + 1) * SPLIT_LINES && self.splits.len() < (lines / SPLIT_LINES) as usize {
+				self.splits.push(self.tick);
+			}
+			if self.lines >= lines {
+				self.finish();
+				return
+			}
+		}
+		match self.mode.timings(self.level) {
+			Some(timings) if !locked_out => {
+				self.entry_delay = if cleared > 0 { timings.line_are } else { timings.are };
+			},
+			_ => self.next_piece(),
+		}
+		if locked_out {
+			self.top_out();
+		}
+	}
+
+	fn next_piece(&mut self) {
+		// Every new piece raises the master level, except at a section stop
+		if self.mode == Mode::Master && !master::section_stop(self.level) {
+			self.advance_level(1);
+		}
+		// At the end of a fixed sequence the held piece is the last one left
+		let kind = match self.hold {
+			Some(held) if self.pieces_left().is_some_and(|left| left <= 1) => {
+				self.hold = None;
+				held
+			},
+			_ => {
+				let kind = self.next;
+				self.next = self.draw_kind();
+				kind
+			},
+		};
+		self.hold_used = false;
+		self.soft_rows = 0;
+		self.spawn(kind);
+	}
+
+	// Raises the master level, noting the time and grade as each 100 levels are passed
+	fn advance_level(&mut self, levels: u16) {
+		let before = self.level;
+		self.level = (self.level + levels).min(MAX_LEVEL);
+		for _ in before / 100..self.level / 100 {
+			self.checkpoints.push(Checkpoint { tick: self.tick, grade: master::grade(self.score) });
+		}
+	}
+
+	fn spawn(&mut self, kind: Kind) {
+		self.lock_timer = 0;
+		self.last_rotated = false;
+		self.piece_presses = 0;
+		self.tetr.set_kind(kind);
+		self.tetr.spawn(&self.board);
+		// Block out: no room for the new piece
+		if !self.tetr.fits(&self.board, self.tetr.rotation, self.tetr.pos.x, self.tetr.pos.y) {
+			self.top_out();
+		}
+	}
+
+	// Pushes the stack up by `count` rows of garbage, the active piece moves up with it
+	pub fn add_garbage(&mut self, count: usize, messy: bool) {
+		if count == 0 || self.over {
+			return
+		}
+		self.rise_garbage(count, messy);
+		self.tetr.pos.y -= count as i32;
+		if !self.tetr.fits(&self.board, self.tetr.rotation, self.tetr.pos.x, self.tetr.pos.y) {
+			self.top_out();
+		}
+	}
+
+	// Pushes the stack up without moving the piece, for garbage that rises between pieces
+	fn rise_garbage(&mut self, count: usize, messy: bool) {
+		let overflow = self.board.push_up(count);
+		fill_garbage(&mut self.board, count, messy, &mut self.rng);
+		self.events.push(GameEvent::GarbageAdded { rows: count as u32 });
+		if overflow {
+			self.top_out();
+		}
+	}
+
+	// Puts an opponent's attack into the versus garbage meter
+	pub fn receive(&mut self, rows: u32) {
+		if rows > 0 && !self.over {
+			self.incoming.push(rows);
+		}
+	}
+
+	// Rows sent since the last call, for handing to the opponent
+	pub fn take_attack(&mut self) -> u32 {
+		std::mem::take(&mut self.attack)
+	}
+
+	pub fn garbage_waiting(&self) -> u32 {
+		self.incoming.iter().sum()
+	}
+
+	// How much of a locked cell to draw, from 1 for all of it to 0 for nothing.
+	// Invisible mode fades blocks out after they lock and shows them all again when the game ends.
+	pub fn visibility(&self, x: usize, y: usize) -> f32 {
+		match self.mode {
+			Mode::Invisible { fade, .. } if !self.over => {
+				let age = self.tick - self.board.set_at(x, y);
+				let fade = fade as u64 * TICKS_PER_SECOND as u64;
+				if age >= fade {
+					0.0
+				} else {
+					1.0 - age as f32 / fade as f32
+				}
+			},
+			_ => 1.0,
+		}
+	}
+
+	// The outline of the hidden stack flashes after a line clear
+	pub fn outline_visible(&self) -> bool {
+		match (self.mode, self.last_clear) {
+			(Mode::Invisible { outline: true, .. }, Some(tick)) => !self.over && self.tick - tick < OUTLINE_TICKS,
+			_ => false,
+		}
+	}
+
+	// Lines left to the next level
+	pub fn goal_left(&self) -> u16 {
+		self.mode.level_goal(self.level) - self.goal_progress
+	}
+
+	// Ends the game with its goal reached, versus also ends the winner's game this way
+	pub fn finish(&mut self) {
+		self.over = true;
+		self.won = true;
+		self.events.push(GameEvent::Finished);
+	}
+
+	fn top_out(&mut self) {
+		if !self.over {
+			self.over = true;
+			self.events.push(GameEvent::ToppedOut);
+		}
+	}
+
+	fn hold(&mut self) {
+		// With the hold empty and the fixed sequence used up there is nothing to swap in
+		if self.hold_used || (self.hold.is_none() && !self.next_shown()) {
+			return
+		}
+		let kind = match self.hold.replace(self.tetr.kind) {
+			Some(kind) => kind,
+			None => {
+				let kind = self.next;
+				self.next = self.draw_kind();
+				kind
+			},
+		};
+		self.spawn(kind);
+		self.hold_used = true;
+		self.events.push(GameEvent::Held);
+	}
+
+	fn check_rows(&self) -> [(usize, bool);4] { // Первый элемент - строка, второй - заполненная она или нет
+		let mut rowsinfo: [(usize, bool);4] = [(0,false);4];
+		let mut num_filled_rows = 0;
+		for y in 0..self.board.rows() {
+			if num_filled_rows >= 4 {
+				eprintln!("warning: num_filled_rows >= 4");
+				break
+			}
+			// assert!(num_filled_rows < 4);
+			rowsinfo[num_filled_rows].0 = y;
+			rowsinfo[num_filled_rows].1 = self.board.row_filled(y);
+			if rowsinfo[num_filled_rows].1 {
+				num_filled_rows+=1;
+			}
+		}
+		rowsinfo
+	}
+
+	fn clear_row(&mut self, row: usize) {
+		for x in 0..self.board.width {
+			self.board.set(x, row, Cell::Empty);
+		}
+		self.lines += 1;
+	}
+
+	fn lower_above(&mut self, row: usize) { // Опускает все вышестоящие строки начиная с row
+
+		for y in (1..row+1).rev() {
+
+			for x in 0..self.board.width {
+				// print!("c: {}, v: {} ", x, self.board.get(x, y));
+				self.board.copy_down(x, y - 1, y);
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use ggez::nalgebra as na;
+
+	fn game(mode: Mode) -> Game {
+		Game::new(1, Board::new(10, 20, 20), &BoardSetup::Empty, Handling::new(), mode)
+	}
+
+	#[test]
+	fn garbage_pushes_stack_and_piece_up() {
+		let mut game = game(Mode::marathon());
+		let floor = game.board.rows() - 1;
+		game.board.set(3, floor, Cell::Block(Kind::T));
+		let y = game.tetr.pos.y;
+		game.add_garbage(2, false);
+		assert_eq!(game.board.get(3, floor - 2), Cell::Block(Kind::T));
+		assert_eq!(game.board.garbage_rows(), 2);
+		assert_eq!(game.tetr.pos.y, y - 2);
+		// Clean garbage keeps the hole in one column
+		let hole = (0..10).find(|x| game.board.get(*x, floor) == Cell::Empty);
+		assert!(hole.is_some());
+		assert_eq!(game.board.get(hole.unwrap(), floor - 1), Cell::Empty);
+		assert!(!game.over);
+	}
+
+	#[test]
+	fn garbage_over_the_top_ends_the_game() {
+		let mut game = game(Mode::marathon());
+		game.board.set(0, 0, Cell::Garbage);
+		game.add_garbage(1, true);
+		assert!(game.over);
+	}
+
+	#[test]
+	fn dig_starts_with_garbage() {
+		let game = game(Mode::Dig { rows: 8, messy: true });
+		assert_eq!(game.board.garbage_rows(), 8);
+	}
+
+	#[test]
+	fn survival_raises_garbage_on_a_timer() {
+		let mut game = game(Mode::Survival);
+		for _ in 0..survival_interval(0) - 1 {
+			game.tick();
+		}
+		assert_eq!(game.board.garbage_rows(), 0);
+		game.tick();
+		assert_eq!((game.board.garbage_rows(), game.rises), (1, 1));
+		assert!(survival_interval(1) < survival_interval(0));
+		assert_eq!(survival_interval(1000), 60);
+	}
+
+	#[test]
+	fn invisible_blocks_fade_after_locking() {
+		let mut game = game(Mode::Invisible { lines: 40, fade: 1, outline: false });
+		let floor = game.board.rows() - 1;
+		game.tick();
+		game.action(GameAction::HardDrop, true);
+		let x = (0..10).find(|x| game.board.get(*x, floor) != Cell::Empty).unwrap();
+		assert_eq!(game.visibility(x, floor), 1.0);
+		for _ in 0..30 {
+			game.tick();
+		}
+		assert_eq!(game.visibility(x, floor), 0.5);
+		for _ in 0..30 {
+			game.tick();
+		}
+		assert_eq!(game.visibility(x, floor), 0.0);
+		game.over = true;
+		assert_eq!(game.visibility(x, floor), 1.0);
+	}
+
+	#[test]
+	fn lock_ticks_move_down_with_cleared_rows() {
+		let mut game = game(Mode::marathon());
+		let floor = game.board.rows() - 1;
+		game.board.tick = 5;
+		game.board.set(0, floor - 1, Cell::Block(Kind::T));
+		for x in 1..10 {
+			game.board.set(x, floor, Cell::Garbage);
+		}
+		game.board.tick = 9;
+		game.board.set(0, floor, Cell::Garbage);
+		game.lock_tetromino();
+		assert_eq!((game.board.get(0, floor), game.board.set_at(0, floor)), (Cell::Block(Kind::T), 5));
+	}
+
+	#[test]
+	fn clearing_the_board_forgets_lock_ticks() {
+		let mut game = game(Mode::marathon());
+		let floor = game.board.rows() - 1;
+		game.board.tick = 7;
+		game.board.set(3, floor, Cell::Garbage);
+		game.board.clear();
+		assert_eq!((game.board.get(3, floor), game.board.set_at(3, floor)), (Cell::Empty, 0));
+	}
+
+	fn puzzle(goal: PuzzleGoal, pieces: &[Kind], row: &str) -> Game {
+		let setup = BoardSetup::Preset(String::from("test"), crate::board_setup::parse_preset(row));
+		let mut game = Game::new(1, Board::new(10, 20, 20), &setup, Handling::new(), Mode::Puzzle(goal));
+		game.set_pieces(pieces);
+		game
+	}
+
+	#[test]
+	fn puzzles_pass_on_their_goal() {
+		let mut game = puzzle(PuzzleGoal::Lines(1), &[Kind::I, Kind::O], "XXXX....XX");
+		assert_eq!((game.tetr.kind, game.next), (Kind::I, Kind::O));
+		game.action(GameAction::HardDrop, true);
+		assert!(game.over && game.won);
+
+		// The hard drop is a key too, so moving the I into the well takes two
+		let mut game = puzzle(PuzzleGoal::Keys { presses: 2, lines: 1 }, &[Kind::I, Kind::I], "XXXXX....X");
+		let x = game.tetr.pos.x;
+		// Shifts are made on the next tick
+		game.action(GameAction::MoveRight, true);
+		game.tick();
+		game.action(GameAction::MoveRight, false);
+		assert_eq!(game.tetr.pos.x, x + 1);
+		game.action(GameAction::HardDrop, true);
+		assert!(game.over && game.won);
+
+		// The same line with a key too many fails on the key count, there are pieces left
+		let mut game = puzzle(PuzzleGoal::Keys { presses: 1, lines: 1 }, &[Kind::I, Kind::I], "XXXXX....X");
+		game.action(GameAction::MoveRight, true);
+		game.tick();
+		game.action(GameAction::MoveRight, false);
+		game.action(GameAction::HardDrop, true);
+		assert_eq!((game.lines, game.presses, game.pieces < game.piece_limit.unwrap()), (1, 2, true));
+		assert!(game.over && !game.won);
+	}
+
+	#[test]
+	fn puzzles_fail_when_pieces_run_out() {
+		let mut game = puzzle(PuzzleGoal::Lines(1), &[Kind::O], "XXXX....XX");
+		assert!(!game.next_shown());
+		game.action(GameAction::HardDrop, true);
+		assert!(game.over && !game.won);
+	}
+
+	#[test]
+	fn holding_never_brings_in_pieces_the_puzzle_doesnt_list() {
+		let mut game = puzzle(PuzzleGoal::Lines(4), &[Kind::I], "XXXXXXXXX.");
+		game.action(GameAction::Hold, true);
+		assert_eq!((game.tetr.kind, game.hold), (Kind::I, None));
+
+		// The held piece comes back once the pieces after it are placed
+		let mut game = puzzle(PuzzleGoal::Lines(4), &[Kind::T, Kind::O], "XXXXXXXXX.");
+		game.action(GameAction::Hold, true);
+		game.action(GameAction::Hold, false);
+		assert_eq!((game.tetr.kind, game.hold, game.next_shown()), (Kind::O, Some(Kind::T), false));
+		game.action(GameAction::HardDrop, true);
+		game.action(GameAction::HardDrop, false);
+		assert_eq!((game.tetr.kind, game.hold, game.over), (Kind::T, None, false));
+		game.action(GameAction::Hold, true);
+		assert_eq!((game.tetr.kind, game.hold), (Kind::T, None));
+		game.action(GameAction::HardDrop, true);
+		assert!(game.over && !game.won);
+	}
+
+	#[test]
+	fn t_spins_need_a_rotation_into_three_corners() {
+		let mut game = game(Mode::marathon());
+		let floor = game.board.rows() - 1;
+		// T pointing down between an overhang and the stack
+		game.board.set(2, floor - 2, Cell::Garbage);
+		game.board.set(2, floor, Cell::Garbage);
+		game.board.set(4, floor, Cell::Garbage);
+		game.tetr.set_kind(Kind::T);
+		game.tetr.rotation = 2;
+		game.tetr.pos = na::Point2::new(2, floor as i32 - 1);
+		assert!(game.tetr.t_corners_blocked(&game.board));
+		game.board.set(2, floor - 2, Cell::Empty);
+		assert!(!game.tetr.t_corners_blocked(&game.board));
+	}
+
+	#[test]
+	fn master_waits_for_lock_delay_and_entry() {
+		let mut game = game(Mode::Master);
+		game.action(GameAction::HardDrop, true);
+		assert_eq!((game.pieces, game.level), (1, 0));
+		let are = master::timings(0).are;
+		assert_eq!(game.entry_delay, are);
+		game.action(GameAction::HardDrop, true);
+		assert_eq!(game.pieces, 1);
+		for _ in 0..are {
+			game.tick();
+		}
+		assert_eq!((game.entry_delay, game.level), (0, 1));
+
+		// At 20G the piece lands at once and waits out the lock delay
+		game.level = 500;
+		game.tick();
+		assert!(!game.tetr.can_fall(&game.board));
+		for _ in 2..master::timings(500).lock {
+			game.tick();
+		}
+		assert_eq!(game.pieces, 1);
+		game.tick();
+		assert_eq!(game.pieces, 2);
+	}
+
+	#[test]
+	fn master_levels_stop_at_sections() {
+		let mut game = game(Mode::Master);
+		game.level = 98;
+		game.next_piece();
+		game.next_piece();
+		assert_eq!(game.level, 99);
+		game.advance_level(2);
+		assert_eq!((game.level, game.checkpoints.len()), (101, 1));
+	}
+}
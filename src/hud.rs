@@ -1,10 +1,21 @@
 use ggez::graphics;
 use ggez::nalgebra as na;
 
+// Numbers shown on the side panel
+pub struct Stats {
+	pub level: u16,
+	pub lines: u16,
+	pub score: u32,
+	pub extra: Option<String>, // mode specific, time or goal
+}
+
 pub struct HUD {
 	font: graphics::Font,
 	level_label: graphics::Text,
 	lines_label: graphics::Text,
+	score_label: graphics::Text,
+	extra_label: graphics::Text, // mode specific, time or goal
+	message_label: graphics::Text,
 }
 
 impl HUD {
@@ -24,16 +35,63 @@ impl HUD {
 				font: Some(font),
 				scale: Some(graphics::Scale::uniform(30.0))
 			}),
+			score_label: graphics::Text::new(graphics::TextFragment{
+				text: String::from(""),
+				color: Some(graphics::Color::from_rgb(200,200,200)),
+				font: Some(font),
+				scale: Some(graphics::Scale::uniform(30.0))
+			}),
+			extra_label: graphics::Text::new(graphics::TextFragment{
+				text: String::from(""),
+				color: Some(graphics::Color::from_rgb(200,200,200)),
+				font: Some(font),
+				scale: Some(graphics::Scale::uniform(30.0))
+			}),
+			message_label: graphics::Text::new(graphics::TextFragment{
+				text: String::from(""),
+				color: Some(graphics::Color::from_rgb(230,60,60)),
+				font: Some(font),
+				scale: Some(graphics::Scale::uniform(40.0))
+			}),
 		}
 	}
 
-	pub fn draw(&mut self, ctx: &mut ggez::Context, pos: &na::Point2::<f32>, level: u16, lines: u16) {
+	pub fn draw(&mut self, ctx: &mut ggez::Context, pos: &na::Point2::<f32>, lines_offset: f32, stats: Stats) {
 		let f = self.level_label.fragments_mut();
-		f[0].text = String::from(format!("Level: {}", level));
+		f[0].text = format!("Level: {}", stats.level);
 		graphics::draw(ctx, &self.level_label, (na::Point2::<f32>::new(pos.x + 10.0, pos.y + 0.0),));
 
+		let f = self.score_label.fragments_mut();
+		f[0].text = format!("Score: {}", stats.score);
+		graphics::draw(ctx, &self.score_label, (na::Point2::<f32>::new(pos.x + 10.0, pos.y + 32.0),));
+
+		if let Some(extra) = stats.extra {
+			let f = self.extra_label.fragments_mut();
+			f[0].text = extra;
+			graphics::draw(ctx, &self.extra_label, (na::Point2::<f32>::new(pos.x + 10.0, pos.y + 64.0),));
+		}
+
 		let f = self.lines_label.fragments_mut();
-		f[0].text = String::from(format!("Lines: {}", lines));
-		graphics::draw(ctx, &self.lines_label, (na::Point2::<f32>::new(pos.x + 10.0, pos.y + 500.0),));
+		f[0].text = format!("Lines: {}", stats.lines);
+		graphics::draw(ctx, &self.lines_label, (na::Point2::<f32>::new(pos.x + 10.0, pos.y + lines_offset),));
+	}
+
+	pub fn draw_message(&mut self, ctx: &mut ggez::Context, pos: &na::Point2::<f32>, message: &str) {
+		let f = self.message_label.fragments_mut();
+		f[0].text = String::from(message);
+		graphics::draw(ctx, &self.message_label, (na::Point2::<f32>::new(pos.x + 10.0, pos.y),));
+	}
+
+	pub fn draw_lines(&self, ctx: &mut ggez::Context, pos: &na::Point2::<f32>, lines: &[String]) -> ggez::GameResult {
+		for (i, line) in lines.iter().enumerate() {
+			let text = graphics::Text::new(graphics::TextFragment{
+				text: line.clone(),
+				color: Some(graphics::Color::from_rgb(200,200,200)),
+				font: Some(self.font),
+				scale: Some(graphics::Scale::uniform(16.0))
+			});
+			graphics::draw(ctx, &text, (na::Point2::<f32>::new(pos.x + 10.0, pos.y + i as f32 * 16.0),))?;
+		}
+		Ok(())
 	}
-}
\ No newline at end of file
+}
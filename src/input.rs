@@ -0,0 +1,299 @@
+// Maps keys and gamepad buttons to game actions
+
+use ggez::event::{KeyCode, Button, Axis};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GameAction {
+	MoveLeft,
+	MoveRight,
+	SoftDrop,
+	HardDrop,
+	RotateCCW,
+	RotateCW,
+	Rotate180,
+	Hold,
+	Pause,
+	Restart,
+}
+
+impl GameAction {
+	pub const ALL: [GameAction; 10] = [
+		GameAction::MoveLeft,
+		GameAction::MoveRight,
+		GameAction::SoftDrop,
+		GameAction::HardDrop,
+		GameAction::RotateCCW,
+		GameAction::RotateCW,
+		GameAction::Rotate180,
+		GameAction::Hold,
+		GameAction::Pause,
+		GameAction::Restart,
+	];
+
+	// Name used in the settings file
+	pub fn name(self) -> &'static str {
+		match self {
+			GameAction::MoveLeft => "move_left",
+			GameAction::MoveRight => "move_right",
+			GameAction::SoftDrop => "soft_drop",
+			GameAction::HardDrop => "hard_drop",
+			GameAction::RotateCCW => "rotate_ccw",
+			GameAction::RotateCW => "rotate_cw",
+			GameAction::Rotate180 => "rotate_180",
+			GameAction::Hold => "hold",
+			GameAction::Pause => "pause",
+			GameAction::Restart => "restart",
+		}
+	}
+
+	pub fn from_name(name: &str) -> Option<GameAction> {
+		GameAction::ALL.iter().cloned().find(|action| action.name() == name)
+	}
+
+	// Label shown on the key config screen
+	pub fn label(self) -> &'static str {
+		match self {
+			GameAction::MoveLeft => "Move left",
+			GameAction::MoveRight => "Move right",
+			GameAction::SoftDrop => "Soft drop",
+			GameAction::HardDrop => "Hard drop",
+			GameAction::RotateCCW => "Rotate left",
+			GameAction::RotateCW => "Rotate right",
+			GameAction::Rotate180 => "Rotate 180",
+			GameAction::Hold => "Hold",
+			GameAction::Pause => "Pause",
+			GameAction::Restart => "Restart",
+		}
+	}
+}
+
+pub struct Bindings<T> {
+	bindings: Vec<(T, GameAction)>,
+}
+
+pub type KeyBindings = Bindings<KeyCode>;
+pub type ButtonBindings = Bindings<Button>;
+
+impl Bindings<KeyCode> {
+	pub fn new() -> Self {
+		Bindings {
+			bindings: vec![
+				(KeyCode::Left, GameAction::MoveLeft),
+				(KeyCode::Right, GameAction::MoveRight),
+				(KeyCode::Down, GameAction::SoftDrop),
+				(KeyCode::Space, GameAction::HardDrop),
+				(KeyCode::A, GameAction::RotateCCW),
+				(KeyCode::S, GameAction::RotateCW),
+				(KeyCode::Up, GameAction::RotateCW),
+				(KeyCode::D, GameAction::Rotate180),
+				(KeyCode::C, GameAction::Hold),
+				(KeyCode::Escape, GameAction::Pause),
+				(KeyCode::R, GameAction::Restart),
+			],
+		}
+	}
+}
+
+impl Bindings<KeyCode> {
+	// Versus splits the keyboard: the first player on the left, the second on the arrows and numpad
+	pub fn versus(player: usize) -> Self {
+		let bindings = if player == 0 {
+			vec![
+				(KeyCode::A, GameAction::MoveLeft),
+				(KeyCode::D, GameAction::MoveRight),
+				(KeyCode::S, GameAction::SoftDrop),
+				(KeyCode::W, GameAction::HardDrop),
+				(KeyCode::Q, GameAction::RotateCCW),
+				(KeyCode::E, GameAction::RotateCW),
+				(KeyCode::Key2, GameAction::Rotate180),
+				(KeyCode::LShift, GameAction::Hold),
+			]
+		} else {
+			vec![
+				(KeyCode::Left, GameAction::MoveLeft),
+				(KeyCode::Right, GameAction::MoveRight),
+				(KeyCode::Down, GameAction::SoftDrop),
+				(KeyCode::Up, GameAction::HardDrop),
+				(KeyCode::Numpad1, GameAction::RotateCCW),
+				(KeyCode::Numpad2, GameAction::RotateCW),
+				(KeyCode::Numpad3, GameAction::Rotate180),
+				(KeyCode::Numpad0, GameAction::Hold),
+			]
+		};
+		Bindings { bindings }
+	}
+}
+
+impl Default for Bindings<KeyCode> {
+	fn default() -> Self {
+		Bindings::<KeyCode>::new()
+	}
+}
+
+impl Bindings<Button> {
+	// D-pad moves, face buttons rotate
+	pub fn new() -> Self {
+		Bindings {
+			bindings: vec![
+				(Button::DPadLeft, GameAction::MoveLeft),
+				(Button::DPadRight, GameAction::MoveRight),
+				(Button::DPadDown, GameAction::SoftDrop),
+				(Button::DPadUp, GameAction::HardDrop),
+				(Button::South, GameAction::RotateCCW),
+				(Button::West, GameAction::RotateCCW),
+				(Button::East, GameAction::RotateCW),
+				(Button::North, GameAction::Rotate180),
+				(Button::LeftTrigger, GameAction::Hold),
+				(Button::RightTrigger, GameAction::Hold),
+				(Button::Start, GameAction::Pause),
+				(Button::Select, GameAction::Restart),
+			],
+		}
+	}
+}
+
+impl Default for Bindings<Button> {
+	fn default() -> Self {
+		Bindings::<Button>::new()
+	}
+}
+
+impl<T: Copy + PartialEq> Bindings<T> {
+	pub fn empty() -> Self {
+		Bindings {
+			bindings: Vec::new(),
+		}
+	}
+
+	pub fn action(&self, input: T) -> Option<GameAction> {
+		self.bindings.iter().find(|(i, _)| *i == input).map(|(_, action)| *action)
+	}
+
+	pub fn inputs(&self, action: GameAction) -> Vec<T> {
+		self.bindings.iter().filter(|(_, a)| *a == action).map(|(input, _)| *input).collect()
+	}
+
+	pub fn add(&mut self, input: T, action: GameAction) {
+		self.bindings.retain(|(i, _)| *i != input);
+		self.bindings.push((input, action));
+	}
+
+	// Makes input the only one of its kind bound to the action
+	pub fn rebind(&mut self, action: GameAction, input: T) {
+		self.bindings.retain(|(_, a)| *a != action);
+		self.add(input, action);
+	}
+
+	// Gives actions that weren't listed and have no inputs the ones they have in
+	// defaults. A listed action without inputs stays unbound.
+	pub fn fill_missing(&mut self, defaults: &Bindings<T>, listed: &[GameAction]) {
+		for action in GameAction::ALL.iter() {
+			if !listed.contains(action) && self.inputs(*action).is_empty() {
+				for input in defaults.inputs(*action) {
+					if self.action(input).is_none() {
+						self.add(input, *action);
+					}
+				}
+			}
+		}
+	}
+}
+
+// Turns analog stick and hat axes into presses and releases of the move and soft drop actions
+pub struct StickInput {
+	pub deadzone: f32,
+	x: i8,
+	y: i8,
+}
+
+impl StickInput {
+	pub fn new(deadzone: f32) -> Self {
+		StickInput {
+			deadzone,
+			x: 0,
+			y: 0,
+		}
+	}
+
+	// Returns (action, pressed) for every action whose state changed
+	pub fn axis(&mut self, axis: Axis, value: f32) -> Vec<(GameAction, bool)> {
+		let dir = if value <= -self.deadzone {
+			-1
+		} else if value >= self.deadzone {
+			1
+		} else {
+			0
+		};
+		let mut changes = Vec::new();
+		match axis {
+			Axis::LeftStickX | Axis::DPadX if dir != self.x => {
+				match self.x {
+					-1 => changes.push((GameAction::MoveLeft, false)),
+					1 => changes.push((GameAction::MoveRight, false)),
+					_ => (),
+				}
+				match dir {
+					-1 => changes.push((GameAction::MoveLeft, true)),
+					1 => changes.push((GameAction::MoveRight, true)),
+					_ => (),
+				}
+				self.x = dir;
+			},
+			// Up is positive, only pulling down is used
+			Axis::LeftStickY | Axis::DPadY => {
+				let down = if dir < 0 { -1 } else { 0 };
+				if down != self.y {
+					changes.push((GameAction::SoftDrop, down < 0));
+					self.y = down;
+				}
+			},
+			_ => (),
+		}
+		changes
+	}
+}
+
+pub fn key_name(key: KeyCode) -> String {
+	format!("{:?}", key)
+}
+
+pub fn key_from_name(name: &str) -> Option<KeyCode> {
+	BINDABLE_KEYS.iter().cloned().find(|key| key_name(*key) == name)
+}
+
+pub fn button_name(button: Button) -> String {
+	format!("{:?}", button)
+}
+
+pub fn button_from_name(name: &str) -> Option<Button> {
+	BINDABLE_BUTTONS.iter().cloned().find(|button| button_name(*button) == name)
+}
+
+const BINDABLE_BUTTONS: [Button; 19] = [
+	Button::South, Button::East, Button::North, Button::West, Button::C, Button::Z,
+	Button::LeftTrigger, Button::LeftTrigger2, Button::RightTrigger, Button::RightTrigger2,
+	Button::Select, Button::Start, Button::Mode, Button::LeftThumb, Button::RightThumb,
+	Button::DPadUp, Button::DPadDown, Button::DPadLeft, Button::DPadRight,
+];
+
+// Keys that can be written to the settings file
+const BINDABLE_KEYS: [KeyCode; 98] = [
+	KeyCode::A, KeyCode::B, KeyCode::C, KeyCode::D, KeyCode::E, KeyCode::F, KeyCode::G,
+	KeyCode::H, KeyCode::I, KeyCode::J, KeyCode::K, KeyCode::L, KeyCode::M, KeyCode::N,
+	KeyCode::O, KeyCode::P, KeyCode::Q, KeyCode::R, KeyCode::S, KeyCode::T, KeyCode::U,
+	KeyCode::V, KeyCode::W, KeyCode::X, KeyCode::Y, KeyCode::Z,
+	KeyCode::Key0, KeyCode::Key1, KeyCode::Key2, KeyCode::Key3, KeyCode::Key4,
+	KeyCode::Key5, KeyCode::Key6, KeyCode::Key7, KeyCode::Key8, KeyCode::Key9,
+	KeyCode::Numpad0, KeyCode::Numpad1, KeyCode::Numpad2, KeyCode::Numpad3, KeyCode::Numpad4,
+	KeyCode::Numpad5, KeyCode::Numpad6, KeyCode::Numpad7, KeyCode::Numpad8, KeyCode::Numpad9,
+	KeyCode::F1, KeyCode::F2, KeyCode::F3, KeyCode::F4, KeyCode::F5, KeyCode::F6,
+	KeyCode::F7, KeyCode::F8, KeyCode::F9, KeyCode::F10, KeyCode::F11, KeyCode::F12,
+	KeyCode::Left, KeyCode::Right, KeyCode::Up, KeyCode::Down,
+	KeyCode::Space, KeyCode::Return, KeyCode::Escape, KeyCode::Tab, KeyCode::Back,
+	KeyCode::Insert, KeyCode::Delete, KeyCode::Home, KeyCode::End, KeyCode::PageUp, KeyCode::PageDown,
+	KeyCode::LShift, KeyCode::RShift, KeyCode::LControl, KeyCode::RControl, KeyCode::LAlt, KeyCode::RAlt,
+	KeyCode::Comma, KeyCode::Period, KeyCode::Slash, KeyCode::Backslash, KeyCode::Semicolon,
+	KeyCode::Apostrophe, KeyCode::LBracket, KeyCode::RBracket, KeyCode::Minus, KeyCode::Equals,
+	KeyCode::Grave, KeyCode::Add, KeyCode::Subtract, KeyCode::Multiply, KeyCode::Divide,
+	KeyCode::Decimal, KeyCode::NumpadEnter, KeyCode::NumpadComma, KeyCode::NumpadEquals,
+];
@@ -0,0 +1,77 @@
+// In-game screen for rebinding keys and gamepad buttons
+
+use ggez::{Context, GameResult};
+use ggez::graphics;
+use ggez::event::{KeyCode, Button};
+use ggez::nalgebra as na;
+
+use crate::input::{GameAction, KeyBindings, ButtonBindings, key_name, button_name};
+
+pub struct KeyConfigScreen {
+	selected: usize,
+	waiting: bool, // next key or button press gets bound to the selected action
+	font: graphics::Font,
+}
+
+impl KeyConfigScreen {
+	pub fn new(ctx: &mut Context) -> Self {
+		KeyConfigScreen {
+			selected: 0,
+			waiting: false,
+			font: graphics::Font::new(ctx, "/arial.ttf").unwrap(),
+		}
+	}
+
+	// Returns true when the screen should be closed
+	pub fn key_down(&mut self, key: KeyCode, keys: &mut KeyBindings) -> bool {
+		if self.waiting {
+			if key != KeyCode::Escape {
+				keys.rebind(GameAction::ALL[self.selected], key);
+			}
+			self.waiting = false;
+			return false
+		}
+		match key {
+			KeyCode::Up => self.selected = (self.selected + GameAction::ALL.len() - 1) % GameAction::ALL.len(),
+			KeyCode::Down => self.selected = (self.selected + 1) % GameAction::ALL.len(),
+			KeyCode::Return => self.waiting = true,
+			KeyCode::Escape => return true,
+			_ => (),
+		}
+		false
+	}
+
+	pub fn button_down(&mut self, button: Button, buttons: &mut ButtonBindings) {
+		if self.waiting {
+			buttons.rebind(GameAction::ALL[self.selected], button);
+			self.waiting = false;
+		}
+	}
+
+	pub fn draw(&self, ctx: &mut Context, keys: &KeyBindings, buttons: &ButtonBindings, pos: na::Point2<f32>) -> GameResult {
+		let title = if self.waiting { "Press a key or button (Esc to cancel)" } else { "Enter to change, Esc to close" };
+		self.draw_line(ctx, title, pos, graphics::Color::from_rgb(200, 200, 200))?;
+		for (i, action) in GameAction::ALL.iter().enumerate() {
+			let mut names: Vec<String> = keys.inputs(*action).into_iter().map(key_name).collect();
+			names.extend(buttons.inputs(*action).into_iter().map(button_name));
+			let color = if i == self.selected {
+				graphics::Color::from_rgb(240, 220, 0)
+			} else {
+				graphics::Color::from_rgb(200, 200, 200)
+			};
+			let text = format!("{}: {}", action.label(), names.join(", "));
+			self.draw_line(ctx, &text, na::Point2::new(pos.x, pos.y + 40.0 + i as f32 * 26.0), color)?;
+		}
+		Ok(())
+	}
+
+	fn draw_line(&self, ctx: &mut Context, text: &str, pos: na::Point2<f32>, color: graphics::Color) -> GameResult {
+		let text = graphics::Text::new(graphics::TextFragment{
+			text: String::from(text),
+			color: Some(color),
+			font: Some(self.font),
+			scale: Some(graphics::Scale::uniform(20.0))
+		});
+		graphics::draw(ctx, &text, (pos,))
+	}
+}
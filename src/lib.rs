@@ -0,0 +1,40 @@
+// Game logic, rendering helpers and settings shared by the game and the tools in src/bin
+
+pub mod tetromino;
+pub mod das;
+pub mod hud;
+pub mod board;
+pub mod board_setup;
+pub mod theme;
+pub mod input;
+pub mod settings;
+pub mod key_config;
+pub mod game;
+pub mod replay;
+pub mod replay_viewer;
+pub mod mode;
+pub mod master;
+pub mod puzzle;
+pub mod puzzle_select;
+pub mod finesse;
+pub mod versus;
+pub mod net;
+pub mod spectate;
+pub mod records;
+
+pub const tetromino_width : usize = 4;
+pub const tetromino_height : usize = 4;
+
+pub fn arg_value(name: &str) -> Option<String> {
+	let mut args = std::env::args().skip(1);
+	while let Some(arg) = args.next() {
+		if arg == name {
+			return args.next();
+		}
+	}
+	None
+}
+
+pub fn arg_flag(name: &str) -> bool {
+	std::env::args().skip(1).any(|arg| arg == name)
+}
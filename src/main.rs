@@ -4,255 +4,731 @@ use ggez::nalgebra as na;
 use ggez::mint::{Point2};
 use ggez::{Context, GameResult};
 use ggez::input::keyboard;
-use ggez::event::{KeyCode, KeyMods};
+use ggez::event::{KeyCode, KeyMods, Button, Axis, GamepadId};
 use ggez::event;
 use ggez::timer;
 
-use std::time;
-use std::time::SystemTime;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tetris::{tetromino_width, tetromino_height, arg_value, arg_flag};
+use tetris::tetromino::Kind;
+use tetris::hud::{HUD, Stats};
+use tetris::board::{Board, Cell};
+use tetris::board_setup::BoardSetup;
+use tetris::theme::{Theme, Skin};
+use tetris::input::{GameAction, StickInput};
+use tetris::settings::Settings;
+use tetris::key_config::KeyConfigScreen;
+use tetris::puzzle::{self, PuzzleGoal};
+use tetris::puzzle_select::PuzzleSelectScreen;
+use tetris::finesse::FinesseTrainer;
+use tetris::versus;
+use tetris::net::{self, NetMatch};
+use tetris::spectate::{self, Broadcast, Spectator};
+use tetris::das::Handling;
+use tetris::game::{self, Game, GameEvent};
+use tetris::replay::{Ending, Replay};
+use tetris::replay_viewer::ReplayViewer;
+use tetris::mode::{Mode, SPLIT_LINES, format_time, format_delta, time_left};
+use tetris::records::{Records, SprintRecord};
+use tetris::master::{self, GRADES};
+
+// Screen geometry derived from the board size
+pub struct Layout {
+	pub cellsize: f32,
+	pub board_width: f32,
+	pub next_tetromino_frame_width: f32,
+	pub next_tetromino_x_offset: f32,
+	pub next_tetromino_y_offset: f32,
+	pub hold_tetromino_y_offset: f32,
+	pub window_width: f32,
+	pub window_height: f32,
+}
+
+impl Layout {
+	pub fn new(board: &Board, cellsize: f32) -> Self {
+		let board_width = board.width as f32 * cellsize;
+		let next_tetromino_frame_width = cellsize * 6.0;
+		Layout {
+			cellsize,
+			board_width,
+			next_tetromino_frame_width,
+			next_tetromino_x_offset: cellsize,
+			next_tetromino_y_offset: cellsize * 5.0,
+			hold_tetromino_y_offset: cellsize * 10.0,
+			window_width: board_width + next_tetromino_frame_width,
+			window_height: board.height as f32 * cellsize,
+		}
+	}
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum GameState {
+	Playing,
+	Paused,
+	GameOver,
+	KeyConfig,
+	PuzzleSelect,
+}
 
-mod tetromino;
-mod das;
-mod hud;
-use tetromino::Tetromino;
-use das::DAS;
-use hud::HUD;
+// One board with its game and recording, versus has two
+struct Player {
+	game: Game,
+	replay: Replay,
+	drawn_pos: na::Point2<i32>, // where the current piece was drawn last frame
+}
 
-pub const grid_cols : usize = 12;
-pub const grid_rows : usize = 30;
-pub const cellsize : f32 = 25f32;
-pub const tetromino_width : usize = 4;
-pub const tetromino_height : usize = 4;
+impl Player {
+	fn new(replay: Replay) -> Self {
+		let game = replay.start_game();
+		Player {
+			drawn_pos: game.tetr.pos,
+			game,
+			replay,
+		}
+	}
+}
 
-pub const next_tetromino_frame_width : f32 = cellsize * 6.0;
-pub const next_tetromino_x_offset : f32 = cellsize;
-pub const next_tetromino_y_offset : f32 = cellsize*5.0;
-pub const window_width : f32 = grid_cols as f32*cellsize + next_tetromino_frame_width;
-pub const window_height : f32 = grid_rows as f32*cellsize;
+// Both versus players get the same seed, and so the same pieces
+fn start_players(board: &Board, handling: Handling, setup: &BoardSetup, mode: Mode, pieces: &[Kind]) -> Vec<Player> {
+	let seed = new_seed();
+	let count = if let Mode::Versus(_) = mode { 2 } else { 1 };
+	(0..count).map(|_| {
+		let mut replay = Replay::new(seed, board, handling, setup.clone(), mode);
+		replay.pieces = pieces.to_vec();
+		Player::new(replay)
+	}).collect()
+}
 
 struct MainState {
-	grid: [[i32; grid_rows]; grid_cols],
-	block_mesh: graphics::Mesh,
-	block_mesh2: graphics::Mesh,
-	clear_mesh: graphics::Mesh,
+	state: GameState,
+	settings: Settings,
+	stick: StickInput,
+	key_config: KeyConfigScreen,
+	puzzles: Option<PuzzleSelectScreen>, // set in puzzle mode, picks the board, pieces and goal
+	finesse: Option<FinesseTrainer>, // --finesse checks every placement for wasted inputs
+	board_setup: BoardSetup,
+	mode: Mode,
+	records: Records,
+	best_sprint: Option<SprintRecord>, // personal best when the game started, for the live comparison
+	players: Vec<Player>,
+	viewer: Option<ReplayViewer>, // set when watching a replay instead of playing
+	net: Option<NetMatch>, // set in an online match, which plays both games in lockstep
+	broadcast: Option<Broadcast>, // the host's spectators
+	spectator: Option<Spectator>, // set when watching an online match, the host sends both players' inputs
+	layout: Layout,
+	skin: Skin,
+	themes: Vec<String>,
 	hud: HUD,
-	tetr: Tetromino,
-	next_tetr: Tetromino,
-	das: DAS,
-	level: u16,
-	lines: u16,
-	tetromino_fall_delay: u32,
-	tetromino_fall_delay_devider: f32,
-	tetromino_fall_delay_normal_devider: f32,
-	tetromino_descreasing_fall_delay_devider: f32,
-	tetromino_normal_fall_delay: u32,
-	das_update_delay: u32,
-	last_das_update_time: u128,
-	last_update_time: u128,
 	need_redraw_all: bool,
-	pressed_down: bool,
-	left_pressed: bool,
-	right_pressed: bool,
 }
 
 impl MainState {
-	fn new(ctx: &mut Context) -> GameResult<MainState> {
+	fn new(ctx: &mut Context, settings: Settings, board: Board, layout: Layout, board_setup: BoardSetup, mode: Mode, theme: &str) -> GameResult<MainState> {
+		let theme = Theme::load(ctx, theme)?;
+		let skin = Skin::new(ctx, theme, layout.cellsize)?;
+		let records = Records::load(ctx).unwrap_or_else(|e| {
+			println!("records: {}", e);
+			Records::new()
+		});
+		let players = start_players(&board, settings.handling, &board_setup, mode, &[]);
 		Ok(MainState{
-			grid: [[0;grid_rows];grid_cols],
-			block_mesh: graphics::Mesh::new_rectangle(
-				ctx, 
-				graphics::DrawMode::fill(), 
-				graphics::Rect{x:2f32,y:2f32,w:cellsize as f32 - 2.0, h:cellsize as f32 - 2.0},
-				graphics::Color::from((12, 123, 213))
-			).unwrap(),
-			block_mesh2: graphics::Mesh::new_circle(
-				ctx, 
-				graphics::DrawMode::fill(), 
-				graphics::mint::Point2{x:5f32,y:5f32},
-				2f32,
-				1f32,
-				graphics::Color::from((255, 255, 254))
-			).unwrap(),
-			clear_mesh: graphics::Mesh::new_rectangle(
-				ctx, 
-				graphics::DrawMode::fill(), 
-				graphics::Rect{x:0.,y:0.,w:cellsize as f32, h:cellsize as f32},
-				graphics::BLACK
-			).unwrap(),
+			state: GameState::Playing,
+			stick: StickInput::new(settings.deadzone),
+			settings,
+			key_config: KeyConfigScreen::new(ctx),
+			puzzles: None,
+			finesse: if arg_flag("--finesse") { Some(FinesseTrainer::new()) } else { None },
+			board_setup,
+			mode,
+			best_sprint: best_sprint(&records, mode),
+			records,
+			players,
+			viewer: None,
+			net: None,
+			broadcast: None,
+			spectator: None,
+			layout,
+			skin,
+			themes: Theme::list(ctx),
 			hud: HUD::new(ctx),
-			tetr: Tetromino::new(),
-			next_tetr: Tetromino::new(),
-			das: DAS::new(),
-			level: 1,
-			lines: 0,
-			tetromino_fall_delay: 60000,
-			tetromino_fall_delay_devider: 8.0,
-			tetromino_fall_delay_normal_devider: 8.0,
-			tetromino_descreasing_fall_delay_devider: 0.3,
-			tetromino_normal_fall_delay: 60000,
-			das_update_delay: 5000,
-			last_das_update_time: 0,
-			last_update_time: 0,
 			need_redraw_all: true,
-			pressed_down: false,
-			left_pressed: false,
-			right_pressed: false,
 		})
 	}
 }
 
+fn best_sprint(records: &Records, mode: Mode) -> Option<SprintRecord> {
+	match mode {
+		Mode::Sprint { lines } => records.sprint(lines).cloned(),
+		_ => None,
+	}
+}
+
+// --seed fixes the piece sequence, otherwise every game gets a new one
+fn new_seed() -> u64 {
+	match arg_value("--seed").and_then(|seed| seed.parse().ok()) {
+		Some(seed) => seed,
+		None => SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(0),
+	}
+}
+
 impl MainState {
-	fn check_rows(&self) -> [(usize, bool);4] { // Первый элемент - строка, второй - заполненная она или нет
-		let mut rowsinfo: [(usize, bool);4] = [(0,false);4];
-		let mut num_filled_rows = 0;
-		for y in 0..grid_rows {
-			if num_filled_rows >= 4 {
-				println!("warning: num_filled_rows >= 4");
-				break
-			}
-			// assert!(num_filled_rows < 4);
-			rowsinfo[num_filled_rows].0 = y;
-			rowsinfo[num_filled_rows].1 = true;
-			for x in 0..grid_cols {
-				if self.grid[x][y] == 0 {
-					rowsinfo[num_filled_rows].1 = false;
-					break;
-				}
-			}
-			if rowsinfo[num_filled_rows].1 {
-				num_filled_rows+=1;
-			}
-		}
-		rowsinfo
+	fn next_theme(&mut self, ctx: &mut Context) -> GameResult {
+		let current = self.themes.iter().position(|name| *name == self.skin.theme.name).unwrap_or(0);
+		let theme = Theme::load(ctx, &self.themes[(current + 1) % self.themes.len()])?;
+		self.skin = Skin::new(ctx, theme, self.layout.cellsize)?;
+		self.need_redraw_all = true;
+		Ok(())
 	}
 
-	fn clear_row(&mut self, row: usize) {
-		for y in 0..grid_cols {
-			self.grid[y][row] = 0;
+	// A game left before it was over is saved as quit, unless nothing was pressed yet
+	fn save_quit(&mut self, ctx: &Context) {
+		let Player { game, replay, .. } = &self.players[0];
+		if self.viewer.is_none() && self.net.is_none() && self.spectator.is_none() && !self.versus() && !game.over && !replay.inputs.is_empty() {
+			self.save_replay(ctx, Ending::Quit);
 		}
-		self.lines += 1;
+	}
 
-		if (self.lines/2 >= self.level && self.lines % 2 == 0) {
-			self.level += 1;
+	fn restart(&mut self) {
+		let board = &self.players[0].game.board;
+		let board = Board::new(board.width, board.height, board.buffer);
+		let mut pieces = Vec::new();
+		if let Some(puzzles) = &self.puzzles {
+			let puzzle = puzzles.current();
+			self.board_setup = puzzle.setup();
+			self.mode = Mode::Puzzle(puzzle.goal);
+			pieces = puzzle.pieces.clone();
 		}
+		self.players = start_players(&board, self.settings.handling, &self.board_setup, self.mode, &pieces);
+		self.best_sprint = best_sprint(&self.records, self.mode);
+		self.state = GameState::Playing;
+		self.need_redraw_all = true;
 	}
 
-	fn lower_above(&mut self, row: usize) { // Опускает все вышестоящие строки начиная с row
+	// Starts the first puzzle of the pack with the select screen open over it
+	fn open_puzzles(&mut self, puzzles: PuzzleSelectScreen) {
+		self.puzzles = Some(puzzles);
+		self.restart();
+		self.state = GameState::PuzzleSelect;
+	}
 
-		for y in (1..row+1).rev() {
+	// Plays or shows a match set up over the network. The window is sized for
+	// the host's board, which the others only learn during the handshake.
+	fn start_net(&mut self, ctx: &mut Context, net: Option<NetMatch>, replays: [Replay; 2]) -> GameResult {
+		let board = replays[0].start_game().board;
+		self.layout = Layout::new(&board, self.layout.cellsize);
+		let (width, height) = (self.layout.window_width * 2.0, self.layout.window_height);
+		graphics::set_drawable_size(ctx, width, height)?;
+		graphics::set_screen_coordinates(ctx, graphics::Rect::new(0.0, 0.0, width, height))?;
+		self.mode = replays[0].mode;
+		let [host, client] = replays;
+		self.players = vec![Player::new(host), Player::new(client)];
+		self.net = net;
+		self.need_redraw_all = true;
+		Ok(())
+	}
 
-			for x in 0..grid_cols {
-				// print!("c: {}, v: {} ", x, self.grid[x as usize][y as usize]);
-				self.grid[x as usize][y as usize] = self.grid[x as usize][(y - 1) as usize];
-			}
+	fn view_replay(&mut self, replay: Replay) {
+		self.best_sprint = best_sprint(&self.records, replay.mode);
+		let mut viewer = ReplayViewer::new(replay.clone());
+		self.players = vec![Player::new(replay)];
+		self.players[0].game = viewer.start();
+		self.viewer = Some(viewer);
+		self.need_redraw_all = true;
+	}
+
+	fn versus(&self) -> bool {
+		self.players.len() > 1
+	}
+
+	// Versus replays are not saved, the garbage between the players isn't recorded
+	fn save_replay(&mut self, ctx: &Context, ending: Ending) {
+		self.players[0].replay.ending = Some(ending);
+		match self.players[0].replay.save(ctx) {
+			Ok(path) => println!("replay saved to {}", path.display()),
+			Err(e) => println!("replay: {}", e),
 		}
 	}
-}
 
-impl event::EventHandler for MainState {
-	fn update(&mut self, ctx: &mut Context) -> GameResult {
-		let fall_delay = (self.tetromino_fall_delay as f32/(self.level as f32/self.tetromino_fall_delay_devider)) as i128;
+	// Hands garbage between the versus players and ends the match when one tops out
+	fn settle(&mut self) {
+		if let [first, second] = self.players.as_mut_slice() {
+			versus::settle(&mut first.game, &mut second.game);
+		}
+	}
 
-		let delta = (SystemTime::now() - timer::time_since_start(ctx)).elapsed().unwrap().as_micros() as i128 - self.last_update_time as i128;
+	fn handle_events(&mut self, ctx: &Context) {
+		for player in 0..self.players.len() {
+			self.handle_player_events(ctx, player);
+		}
+	}
 
-		let das_delta = (SystemTime::now() - timer::time_since_start(ctx)).elapsed().unwrap().as_micros() as i128 - self.last_das_update_time as i128;
+	fn handle_player_events(&mut self, ctx: &Context, player: usize) {
+		let game = &mut self.players[player].game;
+		let events: Vec<GameEvent> = game.events.drain(..).collect();
+		let recorded = self.viewer.is_none() && !self.versus();
+		for event in events {
+			match event {
+				GameEvent::Locked { .. } => {
+					let game = &self.players[player].game;
+					if let (Some(finesse), Some(placement)) = (&mut self.finesse, &game.last_placement) {
+						finesse.placed(placement, game.board.width);
+					}
+					self.need_redraw_all = true;
+				},
+				GameEvent::Held | GameEvent::GoalReached | GameEvent::GarbageAdded { .. } => self.need_redraw_all = true,
+				GameEvent::ToppedOut => {
+					self.state = GameState::GameOver;
+					self.need_redraw_all = true;
+					if recorded {
+						self.save_replay(ctx, Ending::ToppedOut);
+						if let Some(puzzles) = &mut self.puzzles {
+							puzzles.finish(false);
+						}
+					}
+				},
+				GameEvent::Finished => {
+					self.state = GameState::GameOver;
+					self.need_redraw_all = true;
+					if recorded {
+						self.save_replay(ctx, Ending::Finished);
+						self.save_record(ctx);
+						if let Some(puzzles) = &mut self.puzzles {
+							puzzles.finish(true);
+						}
+					}
+				},
+			}
+		}
+	}
 
-		if das_delta > self.das_update_delay as i128 {	
-			self.das.tick();
-			if self.das.need_move {
-				self.das.moved = self.tetr.move_tetromino(&mut self.grid, self.das.side);
-				if self.das.moved {
-					self.das.need_move = false;
+	fn save_record(&mut self, ctx: &Context) {
+		let game = &self.players[0].game;
+		if let Mode::Sprint { lines } = game.mode {
+			let record = SprintRecord { lines, ticks: game.tick, splits: game.splits.clone() };
+			if self.records.submit_sprint(record) {
+				if let Err(e) = self.records.save(ctx) {
+					println!("records: {}", e);
 				}
 			}
-			self.last_das_update_time = (SystemTime::now() - timer::time_since_start(ctx)).elapsed().unwrap().as_micros();
 		}
+	}
 
-		if delta > fall_delay {
-			self.last_update_time = (SystemTime::now() - timer::time_since_start(ctx)).elapsed().unwrap().as_micros();// + (delta as u128 - self.tetromino_fall_delay as u128);
-			
-			if self.tetr.fall(&mut self.grid) {
-				self.das.new_tetromino();
-				self.tetromino_fall_delay_devider = self.tetromino_fall_delay_normal_devider;
-				self.need_redraw_all = true;
-				self.tetr.blocks = self.next_tetr.blocks;
-				self.next_tetr.reset();
-			} else {
-				self.das.fall();
-			}
-			let rowsinfo = self.check_rows();
-			for row in rowsinfo.iter() {
-				if row.1 {
-					self.clear_row(row.0);
-					self.lower_above(row.0);
+	// Mode specific lines for the side panel
+	fn mode_status(&self, game: &Game) -> Vec<String> {
+		match game.mode {
+			Mode::Marathon { .. } if game.won && !game.over => vec![String::from("Goal reached!")],
+			Mode::Marathon { .. } | Mode::Ultra { .. } => Vec::new(),
+			Mode::Survival => vec![
+				String::from("Survival"),
+				format!("Time {}", format_time(game.tick)),
+				format!("Next row {}", format_time(game.rise_timer)),
+			],
+			Mode::Puzzle(goal) => {
+				let mut status = vec![String::from("Puzzle"), goal.describe()];
+				if let PuzzleGoal::Keys { .. } = goal {
+					status.push(format!("Keys {}", game.presses));
+				}
+				status
+			},
+			Mode::Invisible { fade, .. } => vec![
+				String::from(if fade == 0 { "Invisible" } else { "Fading" }),
+				format!("Time {}", format_time(game.tick)),
+			],
+			Mode::Versus(_) => vec![
+				String::from("Versus"),
+				format!("Incoming {}", game.garbage_waiting()),
+			],
+			Mode::Master => {
+				let grade = master::grade(game.score);
+				let mut status = vec![
+					format!("Grade {}", GRADES[grade]),
+					format!("Time {}", format_time(game.tick)),
+				];
+				if let Some(score) = master::next_grade_score(grade) {
+					status.push(format!("Next grade {}", score));
+				}
+				status
+			},
+			Mode::Dig { rows, .. } => vec![
+				format!("Dig {}", rows),
+				format!("Time {}", format_time(game.tick)),
+			],
+			Mode::Sprint { lines } => {
+				let mut status = vec![
+					format!("Sprint {}", lines),
+					format!("Time {}", format_time(game.tick)),
+				];
+				let split = game.splits.len();
+				match &self.best_sprint {
+					Some(best) if game.won => {
+						let verdict = if game.tick < best.ticks { "New best!" } else { "Best" };
+						status.push(format!("{} {}", verdict, format_delta(game.tick, best.ticks)));
+					},
+					None if game.won => status.push(String::from("New best!")),
+					Some(best) if split > 0 && split <= best.splits.len() => {
+						let delta = format_delta(game.splits[split - 1], best.splits[split - 1]);
+						let side = if game.splits[split - 1] <= best.splits[split - 1] { "ahead" } else { "behind" };
+						status.push(format!("{} lines {} {}", split as u16 * SPLIT_LINES, delta, side));
+					},
+					_ => (),
 				}
+				status
+			},
+		}
+	}
+
+	// Third HUD label: time left in timed modes, garbage left in dig, lines to the next level otherwise
+	fn hud_extra(&self, game: &Game) -> Option<String> {
+		match (time_left(game.mode, game.tick), game.mode) {
+			(Some(ticks), _) => Some(format!("Time: {}", format_time(ticks))),
+			(None, Mode::Dig { .. }) => Some(format!("Garbage: {}", game.board.garbage_rows())),
+			(None, Mode::Puzzle(_)) => Some(format!("Pieces: {}", game.piece_limit.unwrap_or(0).saturating_sub(game.pieces))),
+			(None, Mode::Invisible { lines, .. }) => Some(format!("Left: {}", lines.saturating_sub(game.lines))),
+			(None, Mode::Master) => Some(format!("Stop: {}", master::next_stop(game.level))),
+			(None, Mode::Versus(_)) => Some(format!("Sent: {}", game.sent)),
+			(None, _) => Some(format!("Goal: {}", game.goal_left())),
+		}
+	}
+
+	// Score breakdown shown when a timed game ends
+	fn results(&self, game: &Game) -> Vec<String> {
+		let names = ["", "Singles", "Doubles", "Triples", "Tetrises"];
+		let mut results = Vec::new();
+		for (clear, name) in names.iter().enumerate().skip(1) {
+			results.push(format!("{} x{}: {}", name, game.clears[clear], game.clear_points[clear]));
+		}
+		results.push(format!("Drops: {}", game.drop_points));
+		results.push(format!("Total: {}", game.score));
+		results
+	}
+
+	// Player and action for a key. In versus each player has a map of their own,
+	// pause and restart come from the usual one. Gamepads play for the first player.
+	fn key_action(&self, key: KeyCode) -> Option<(usize, GameAction)> {
+		if let Some(net) = &self.net {
+			return self.settings.keys.action(key).map(|action| (net.local, action))
+		}
+		if !self.versus() {
+			return self.settings.keys.action(key).map(|action| (0, action))
+		}
+		match self.settings.keys.action(key) {
+			Some(action @ GameAction::Pause) | Some(action @ GameAction::Restart) => Some((0, action)),
+			_ => self.settings.versus_keys.iter().enumerate().find_map(|(player, keys)| keys.action(key).map(|action| (player, action))),
+		}
+	}
+
+	fn action(&mut self, ctx: &mut Context, player: usize, action: GameAction, pressed: bool) {
+		if self.viewer.is_some() || self.spectator.is_some() {
+			return
+		}
+		// Online matches can't be paused or restarted, the inputs go to the other side as well
+		if let Some(net) = &mut self.net {
+			if self.state == GameState::Playing && action != GameAction::Pause && action != GameAction::Restart {
+				net.input(action, pressed);
 			}
+			return
 		}
+		match (self.state, action, pressed) {
+			(_, GameAction::Restart, true) => {
+				self.save_quit(ctx);
+				self.restart();
+			},
+			(GameState::Playing, GameAction::Pause, true) => {
+				self.state = GameState::Paused;
+				self.need_redraw_all = true;
+			},
+			(GameState::Paused, GameAction::Pause, true) => {
+				self.state = GameState::Playing;
+				self.need_redraw_all = true;
+			},
+			(_, GameAction::Pause, _) | (_, GameAction::Restart, _) => (),
+			// Releases always reach the game so nothing stays held after a pause, the key config or the puzzle select screen
+			(GameState::Playing, action, pressed) | (GameState::Paused, action, pressed @ false)
+				| (GameState::KeyConfig, action, pressed @ false) | (GameState::PuzzleSelect, action, pressed @ false) => {
+				let Player { game, replay, .. } = &mut self.players[player];
+				replay.record(game.tick, action, pressed);
+				game.action(action, pressed);
+				self.settle();
+				self.handle_events(ctx);
+			},
+			_ => (),
+		}
+	}
 
+	fn draw_messages(&mut self, ctx: &mut Context, player: usize) -> GameResult {
+		let game = &self.players[player].game;
+		let message_pos = na::Point2::<f32>::new(0.0, self.layout.window_height / 2.0 - 20.0);
+		if let Some(viewer) = &self.viewer {
+			if viewer.paused && !game.over {
+				self.hud.draw_message(ctx, &message_pos, "Paused");
+			}
+		}
+		let countdown = self.net.as_ref().map(NetMatch::countdown_left).or_else(|| self.spectator.as_ref().map(Spectator::countdown_left));
+		if let Some(countdown @ 1..) = countdown {
+			let seconds = countdown.div_ceil(game::TICKS_PER_SECOND as u64);
+			self.hud.draw_message(ctx, &message_pos, &seconds.to_string());
+		}
+		let disconnected = self.net.as_ref().and_then(|net| net.disconnected.as_ref())
+			.or_else(|| self.spectator.as_ref().and_then(|spectator| spectator.disconnected.as_ref())).cloned();
+		if let (Some(reason), false) = (disconnected, game.over) {
+			self.hud.draw_message(ctx, &message_pos, "Disconnected");
+			self.hud.draw_lines(ctx, &na::Point2::<f32>::new(0.0, message_pos.y + 50.0), std::slice::from_ref(&reason))?;
+			return Ok(())
+		}
+		match self.state {
+			GameState::GameOver if game.won => {
+				match game.mode {
+					Mode::Ultra { .. } => {
+						self.hud.draw_message(ctx, &message_pos, "Time up");
+						self.hud.draw_lines(ctx, &na::Point2::<f32>::new(0.0, message_pos.y + 50.0), &self.results(game))?;
+					},
+					Mode::Master => {
+						let grade = master::final_grade(game.score, game.tick, &game.checkpoints);
+						self.hud.draw_message(ctx, &message_pos, &format!("Grade {}", GRADES[grade]));
+					},
+					Mode::Puzzle(_) => {
+						self.hud.draw_message(ctx, &message_pos, "Solved");
+						self.hud.draw_lines(ctx, &na::Point2::<f32>::new(0.0, message_pos.y + 50.0), &[String::from("R for the next puzzle, F3 to pick one")])?;
+					},
+					Mode::Versus(_) => self.hud.draw_message(ctx, &message_pos, "Winner"),
+					_ => self.hud.draw_message(ctx, &message_pos, "Finished"),
+				}
+			},
+			GameState::GameOver => {
+				if let Mode::Puzzle(_) = game.mode {
+					self.hud.draw_message(ctx, &message_pos, "Failed");
+					self.hud.draw_lines(ctx, &na::Point2::<f32>::new(0.0, message_pos.y + 50.0), &[String::from("R to retry, F3 to pick a puzzle")])?;
+				} else {
+					self.hud.draw_message(ctx, &message_pos, "Game over");
+				}
+			},
+			GameState::Paused => self.hud.draw_message(ctx, &message_pos, "Paused"),
+			_ => (),
+		}
 		Ok(())
 	}
 
-	fn draw(&mut self, ctx: &mut Context) -> GameResult {
-		let mut draw_region = (self.tetr.pos.x - 1, self.tetr.pos.y - 1, self.tetr.pos.x + 5, self.tetr.pos.y+4);
-		if self.need_redraw_all {
-			graphics::clear(ctx, ggez::graphics::BLACK);
-			draw_region = (0, 0, window_width as i32, window_height as i32);
-			self.need_redraw_all = false;
+	// Edges between the hidden stack and empty cells
+	fn draw_outline(&self, ctx: &mut Context, board: &Board) -> GameResult {
+		let cellsize = self.layout.cellsize;
+		let mut builder = graphics::MeshBuilder::new();
+		let mut edges = 0;
+		for y in board.buffer..board.rows() {
+			for x in 0..board.width {
+				if board.get(x, y) == Cell::Empty {
+					continue;
+				}
+				let (left, top) = (x as f32 * cellsize, (y - board.buffer) as f32 * cellsize);
+				let (right, bottom) = (left + cellsize, top + cellsize);
+				let sides = [
+					((-1, 0), [left, top, left, bottom]),
+					((1, 0), [right, top, right, bottom]),
+					((0, -1), [left, top, right, top]),
+					((0, 1), [left, bottom, right, bottom]),
+				];
+				for ((dx, dy), line) in sides.iter() {
+					if board.get_checked(x as i32 + dx, y as i32 + dy) == Some(Cell::Empty) {
+						builder.line(&[na::Point2::new(line[0], line[1]), na::Point2::new(line[2], line[3])], 2.0, graphics::WHITE)?;
+						edges += 1;
+					}
+				}
+			}
+		}
+		// A mesh without any lines can't be built
+		if edges == 0 {
+			return Ok(())
+		}
+		let mesh = builder.build(ctx)?;
+		graphics::draw(ctx, &mesh, (na::Point2::<f32>::new(0.0, 0.0),))
+	}
 
-			let next_tetr_frame_mesh = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::fill(), graphics::Rect{x:0.,y:0.,w:next_tetromino_frame_width, h:grid_cols as f32 * cellsize}, graphics::BLACK).unwrap();
-			let next_tetr_frame_mesh2 = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::stroke(2.0), graphics::Rect{x:0.,y:0.,w:next_tetromino_frame_width, h:grid_rows as f32 * cellsize}, graphics::WHITE).unwrap();
-			graphics::draw(ctx, &next_tetr_frame_mesh, (na::Point2::<f32>::new(grid_cols as f32 *cellsize, 0.0),));
-			graphics::draw(ctx, &next_tetr_frame_mesh2, (na::Point2::<f32>::new(grid_cols as f32 *cellsize, 0.0),));
+	fn draw_player(&mut self, ctx: &mut Context, player: usize, redraw_all: bool) -> GameResult {
+		let cellsize = self.layout.cellsize;
+		let Player { game, drawn_pos, .. } = &mut self.players[player];
+		let pos = game.tetr.pos;
+		// Cover both the old and the new piece position, the piece can move several rows between frames
+		let mut draw_region = (pos.x.min(drawn_pos.x) - 1, pos.y.min(drawn_pos.y) - 1, pos.x.max(drawn_pos.x) + 5, pos.y.max(drawn_pos.y) + 4);
+		*drawn_pos = pos;
+		let game = &self.players[player].game;
+		let buffer = game.board.buffer as i32;
+		if redraw_all {
+			draw_region = (0, 0, game.board.width as i32, game.board.rows() as i32);
+
+			let frame_rect = graphics::Rect{x:0.,y:0.,w:self.layout.next_tetromino_frame_width, h:self.layout.window_height};
+			let next_tetr_frame_mesh = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::fill(), frame_rect, self.skin.theme.panel).unwrap();
+			let next_tetr_frame_mesh2 = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::stroke(2.0), frame_rect, self.skin.theme.panel_border).unwrap();
+			graphics::draw(ctx, &next_tetr_frame_mesh, (na::Point2::<f32>::new(self.layout.board_width, 0.0),));
+			graphics::draw(ctx, &next_tetr_frame_mesh2, (na::Point2::<f32>::new(self.layout.board_width, 0.0),));
 
 			// let tetr_mesh = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::fill(), graphics::Rect{x:0.,y:0.,w:cellsize, h:cellsize}, graphics::WHITE).unwrap();
 
-			for x in 0..tetromino_width {
-				for y in 0..tetromino_height {
-					if self.next_tetr.blocks[0][y][x] == 1 {
-						let blockX = x as f32 * cellsize;
-						let blockY = y as f32 * cellsize;
-						graphics::draw(ctx, &self.block_mesh, (na::Point2::<f32>::new(blockX + grid_cols as f32*cellsize + next_tetromino_x_offset, blockY + next_tetromino_y_offset),));
-						graphics::draw(ctx, &self.block_mesh2, (na::Point2::<f32>::new(blockX + grid_cols as f32*cellsize + next_tetromino_x_offset, blockY + next_tetromino_y_offset),));
-					}
-				}
+			if game.next_shown() {
+				self.draw_preview(ctx, game.next, self.layout.next_tetromino_y_offset)?;
+			}
+			if let Some(kind) = game.hold {
+				self.draw_preview(ctx, kind, self.layout.hold_tetromino_y_offset)?;
 			}
 
 			// draw HUD
-			self.hud.draw(ctx, &na::Point2::<f32>::new(grid_cols as f32 * cellsize, 30.0), self.level, self.lines);
-
-		}
-		if self.pressed_down {
-			draw_region.1 = 0;
+			let stats = Stats { level: game.level, lines: game.lines, score: game.score, extra: self.hud_extra(game) };
+			self.hud.draw(ctx, &na::Point2::<f32>::new(self.layout.board_width, 30.0), self.layout.window_height - 100.0, stats);
+			let mut status = self.mode_status(game);
+			if let Mode::Versus(_) = game.mode {
+				self.draw_meter(ctx, game.garbage_waiting())?;
+			}
+			if let Some(finesse) = &self.finesse {
+				status.extend(finesse.overlay());
+			}
+			if let Some(net) = &self.net {
+				status.push(String::from(if player == net.local { "You" } else { "Opponent" }));
+			}
+			if let (Some(broadcast), 0) = (&self.broadcast, player) {
+				status.push(format!("Spectators {}", broadcast.watchers()));
+			}
+			if self.spectator.is_some() {
+				status.push(format!("Player {}", player + 1));
+			}
+			if let Some(viewer) = &self.viewer {
+				status.extend(viewer.overlay(game));
+			}
+			let status_pos = na::Point2::<f32>::new(self.layout.board_width, self.layout.hold_tetromino_y_offset + cellsize * 3.0);
+			self.hud.draw_lines(ctx, &status_pos, &status)?;
 		}
 		
 		for x in draw_region.0..draw_region.2 {
 			for y in draw_region.1..draw_region.3 {
-				if x as usize >= grid_cols || y as usize >= grid_rows || x < 0 || y < 0 {
+				if x as usize >= game.board.width || y as usize >= game.board.rows() || x < 0 || y < buffer {
 					continue;
 				}
-				if self.grid[x as usize][y as usize] == 1 {
-					graphics::draw(ctx, &self.block_mesh, (na::Point2::<f32>::new(x as f32 * cellsize as f32, y as f32 * cellsize as f32),));
-					graphics::draw(ctx, &self.block_mesh2,(na::Point2::<f32>::new(x as f32 * cellsize as f32, y as f32 * cellsize as f32),));
-				} else {
-					graphics::draw(ctx, &self.clear_mesh, (na::Point2::<f32>::new(x as f32 * cellsize as f32, y as f32 * cellsize as f32),));
+				let screen_pos = na::Point2::<f32>::new(x as f32 * cellsize, (y - buffer) as f32 * cellsize);
+				let visibility = game.visibility(x as usize, y as usize);
+				self.skin.draw_faded(ctx, game.board.get(x as usize, y as usize), screen_pos, visibility)?;
+			}
+		}
+		if game.outline_visible() {
+			self.draw_outline(ctx, &game.board)?;
+		}
+
+		// let debug_mesh = das::DasDebug::debug_mesh(ctx, &game.das, cellsize as f32);
+
+		for x in 0..tetromino_width {
+			for y in 0..tetromino_height {
+				if game.tetr.blocks[game.tetr.rotation][y][x] == 1 && game.board.is_visible(game.tetr.pos.y + y as i32) {
+					let blockX = (game.tetr.pos.x + x as i32) as f32 * cellsize;
+					let blockY = (game.tetr.pos.y + y as i32 - buffer) as f32 * cellsize;
+					self.skin.draw_block(ctx, game.tetr.kind, na::Point2::<f32>::new(blockX, blockY))?;
 				}
 			}
 		}
 
-		let mut tetromino_mesh = &self.block_mesh;
-		// let debug_mesh = das::DAS_DEBUG::debug_mesh(ctx, &self.das, cellsize as f32);
-		// tetromino_mesh = &debug_mesh;
+		// Messages go over the board, so they are drawn last
+		if redraw_all {
+			self.draw_messages(ctx, player)?;
+		}
+		Ok(())
+	}
+
+	// Incoming versus garbage as a bar along the left edge of the panel, a cell high per row
+	fn draw_meter(&self, ctx: &mut Context, rows: u32) -> GameResult {
+		if rows == 0 {
+			return Ok(())
+		}
+		let cellsize = self.layout.cellsize;
+		let height = (rows as f32 * cellsize).min(self.layout.window_height);
+		let rect = graphics::Rect { x: 0.0, y: 0.0, w: cellsize / 3.0, h: height };
+		let mesh = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::fill(), rect, graphics::Color::from_rgb(220, 40, 40))?;
+		graphics::draw(ctx, &mesh, (na::Point2::<f32>::new(self.layout.board_width + 2.0, self.layout.window_height - height),))
+	}
 
+	fn draw_preview(&self, ctx: &mut Context, kind: Kind, y_offset: f32) -> GameResult {
+		let blocks = kind.blocks();
 		for x in 0..tetromino_width {
 			for y in 0..tetromino_height {
-				if self.tetr.blocks[self.tetr.rotation][y][x] == 1 {
-					let blockX = (self.tetr.pos.x + x as i32) as f32 * cellsize as f32;
-					let blockY = (self.tetr.pos.y + y as i32) as f32 * cellsize as f32;
-					graphics::draw(ctx, tetromino_mesh, (na::Point2::<f32>::new(blockX, blockY),));
-					graphics::draw(ctx, &self.block_mesh2, (na::Point2::<f32>::new(blockX, blockY),));
+				if blocks[0][y][x] == 1 {
+					let block_x = x as f32 * self.layout.cellsize + self.layout.board_width + self.layout.next_tetromino_x_offset;
+					let block_y = y as f32 * self.layout.cellsize + y_offset;
+					self.skin.draw_block(ctx, kind, na::Point2::<f32>::new(block_x, block_y))?;
 				}
 			}
 		}
+		Ok(())
+	}
+}
+
+impl event::EventHandler for MainState {
+	fn update(&mut self, ctx: &mut Context) -> GameResult {
+		while timer::check_update_time(ctx, game::TICKS_PER_SECOND) {
+			if let (Some(net), [first, second]) = (&mut self.net, self.players.as_mut_slice()) {
+				let played = net.tick(&mut first.game, &mut second.game);
+				if let Some(broadcast) = &mut self.broadcast {
+					broadcast.frames(played, &first.game, &second.game);
+				}
+			} else if let (Some(spectator), [first, second]) = (&mut self.spectator, self.players.as_mut_slice()) {
+				spectator.tick(&mut first.game, &mut second.game);
+			} else if let Some(viewer) = &mut self.viewer {
+				viewer.frame(&mut self.players[0].game);
+			} else if self.state == GameState::Playing {
+				for player in self.players.iter_mut() {
+					player.game.tick();
+				}
+				self.settle();
+			}
+		}
+		self.handle_events(ctx);
+		// Spectators have no say in the match, it ends with the games they play back
+		let watched_over = self.spectator.as_ref().is_some_and(|spectator| spectator.disconnected.is_some() || self.players.iter().any(|player| player.game.over));
+		if (watched_over || self.net.as_ref().is_some_and(|net| net.disconnected.is_some())) && self.state == GameState::Playing {
+			self.state = GameState::GameOver;
+			self.need_redraw_all = true;
+		}
+		Ok(())
+	}
+
+	fn draw(&mut self, ctx: &mut Context) -> GameResult {
+		if self.state == GameState::KeyConfig {
+			graphics::clear(ctx, self.skin.theme.background);
+			self.key_config.draw(ctx, &self.settings.keys, &self.settings.buttons, na::Point2::<f32>::new(10.0, 10.0))?;
+			graphics::present(ctx)?;
+			timer::yield_now();
+			return Ok(())
+		}
+		if let (GameState::PuzzleSelect, Some(puzzles)) = (self.state, &self.puzzles) {
+			graphics::clear(ctx, self.skin.theme.background);
+			puzzles.draw(ctx, na::Point2::<f32>::new(10.0, 10.0))?;
+			graphics::present(ctx)?;
+			timer::yield_now();
+			return Ok(())
+		}
+
+		// The overlay and timers change every frame
+		let timed = match self.mode {
+			Mode::Sprint { .. } | Mode::Ultra { .. } | Mode::Dig { .. } | Mode::Survival | Mode::Master => true,
+			// Fading blocks change every frame, and a partial redraw would bring back invisible ones
+			Mode::Invisible { .. } => true,
+			// The key count changes with every press
+			Mode::Puzzle(_) => true,
+			// So does the garbage meter
+			Mode::Versus(_) => true,
+			Mode::Marathon { .. } => false,
+		};
+		if self.viewer.is_some() || (self.state == GameState::Playing && timed) {
+			self.need_redraw_all = true;
+		}
+		let redraw_all = self.need_redraw_all;
+		self.need_redraw_all = false;
+		if redraw_all {
+			graphics::clear(ctx, self.skin.theme.background);
+		}
+		// Each board is drawn at the origin, moved over by a board and panel per player
+		for player in 0..self.players.len() {
+			let offset = na::Point2::<f32>::new(player as f32 * self.layout.window_width, 0.0);
+			graphics::push_transform(ctx, Some(graphics::DrawParam::new().dest(offset).to_matrix()));
+			graphics::apply_transformations(ctx)?;
+			self.draw_player(ctx, player, redraw_all)?;
+			graphics::pop_transform(ctx);
+			graphics::apply_transformations(ctx)?;
+		}
 
 		graphics::present(ctx);
 		timer::yield_now();
@@ -260,59 +736,97 @@ impl event::EventHandler for MainState {
 		Ok(())
 	}
 
-	fn key_down_event(&mut self, ctx: &mut Context, key: KeyCode, mods: KeyMods, _: bool) {
-		match key {
-			KeyCode::Left => {
-				if !self.das.moving {
-					self.das.need_move = true;
+	fn key_down_event(&mut self, ctx: &mut Context, key: KeyCode, _mods: KeyMods, repeat: bool) {
+		if self.state == GameState::KeyConfig {
+			if self.key_config.key_down(key, &mut self.settings.keys) {
+				if let Err(e) = self.settings.save(ctx) {
+					println!("settings: {}", e);
 				}
-				if !self.left_pressed {
-					self.das.start_moving(-1);
+				self.state = GameState::Paused;
+				self.need_redraw_all = true;
+			}
+			return
+		}
+		if self.state == GameState::PuzzleSelect {
+			if key == KeyCode::Escape {
+				self.state = if self.players[0].game.over { GameState::GameOver } else { GameState::Paused };
+			} else if self.puzzles.as_mut().is_some_and(|puzzles| puzzles.key_down(key)) {
+				self.save_quit(ctx);
+				self.restart();
+			}
+			self.need_redraw_all = true;
+			return
+		}
+		if let Some(viewer) = &mut self.viewer {
+			if key == KeyCode::Escape {
+				event::quit(ctx);
+			}
+			let game = &mut self.players[0].game;
+			viewer.key_down(key, game);
+			game.events.clear();
+			self.state = if game.over { GameState::GameOver } else { GameState::Playing };
+			self.need_redraw_all = true;
+			return
+		}
+		if repeat {
+			return
+		}
+		match key {
+			KeyCode::F1 => {
+				if self.state != GameState::GameOver {
+					self.state = GameState::KeyConfig;
 				}
-				self.left_pressed = true;
 			},
-			KeyCode::Right => {
-				if !self.das.moving {
-					self.das.need_move = true;
+			KeyCode::F3 => {
+				if self.puzzles.is_some() {
+					self.state = GameState::PuzzleSelect;
 				}
-				if !self.right_pressed {
-					self.das.start_moving(1);
+			},
+			KeyCode::F2 => {
+				if let Err(e) = self.next_theme(ctx) {
+					println!("theme: {}", e);
 				}
-				self.right_pressed = true;
 			},
-			KeyCode::A => self.tetr.rotate(&self.grid, -1),
-			KeyCode::S => self.tetr.rotate(&self.grid, 1),
-			KeyCode::Down => {
-				// self.tetromino_fall_delay = self.tetromino_normal_fall_delay - self.tetromino_decreasing_fall_delay;
-				if !self.pressed_down {
-					self.tetromino_fall_delay_devider = self.tetromino_descreasing_fall_delay_devider;
-					self.pressed_down = true;
+			key => {
+				if let Some((player, action)) = self.key_action(key) {
+					self.action(ctx, player, action, true);
 				}
 			},
-			_ => (),
-		};
+		}
 	}
 
-	fn key_up_event(&mut self, ctx: &mut Context, key: KeyCode, mods: KeyMods) {
-		match key {
-			KeyCode::Left => {
-				self.das.stop_moving();
-				self.left_pressed = false;
-			},
-			KeyCode::Right => {
-				self.das.stop_moving();
-				self.right_pressed = false;
-			},
-			KeyCode::Down => {
-				// self.tetromino_fall_delay = self.tetromino_normal_fall_delay;
-				self.tetromino_fall_delay_devider = self.tetromino_fall_delay_normal_devider;
-				self.pressed_down = false;
-			},
-			_ => (),
-		};
+	fn key_up_event(&mut self, ctx: &mut Context, key: KeyCode, _mods: KeyMods) {
+		if let Some((player, action)) = self.key_action(key) {
+			self.action(ctx, player, action, false);
+		}
+	}
+
+	fn gamepad_button_down_event(&mut self, ctx: &mut Context, btn: Button, _id: GamepadId) {
+		if self.state == GameState::KeyConfig {
+			self.key_config.button_down(btn, &mut self.settings.buttons);
+			return
+		}
+		if let Some(action) = self.settings.buttons.action(btn) {
+			self.action(ctx, 0, action, true);
+		}
+	}
+
+	fn gamepad_button_up_event(&mut self, ctx: &mut Context, btn: Button, _id: GamepadId) {
+		if let Some(action) = self.settings.buttons.action(btn) {
+			self.action(ctx, 0, action, false);
+		}
+	}
+
+	fn gamepad_axis_event(&mut self, ctx: &mut Context, axis: Axis, value: f32, _id: GamepadId) {
+		for (action, pressed) in self.stick.axis(axis, value) {
+			self.action(ctx, 0, action, pressed);
+		}
 	}
 
-	
+	fn quit_event(&mut self, ctx: &mut Context) -> bool {
+		self.save_quit(ctx);
+		false
+	}
 }
 
 fn main() -> GameResult {
@@ -324,9 +838,27 @@ fn main() -> GameResult {
 		srgb: true,
 	};
 
+	let replay = match arg_value("--replay") {
+		Some(path) => Some(Replay::load(&path).map_err(|e| ggez::GameError::ResourceLoadError(format!("{}: {}", path, e)))?),
+		None => None,
+	};
+	let board = match &replay {
+		Some(replay) => Board::new(replay.width, replay.height, replay.buffer),
+		None => Board::from_args(),
+	};
+	let layout = Layout::new(&board, 25.0);
+	let mode = Mode::from_args();
+	// Versus puts a second board and panel to the right of the first
+	let online = ["--host", "--connect", "--watch"].iter().any(|arg| arg_value(arg).is_some());
+	let boards = match (&replay, mode) {
+		(None, Mode::Versus(_)) => 2.0,
+		(None, _) if online => 2.0,
+		_ => 1.0,
+	};
+
 	let windowmode = ggez::conf::WindowMode {
-		width: window_width,
-		height: window_height,
+		width: layout.window_width * boards,
+		height: layout.window_height,
 		maximized: false,
 		fullscreen_type: ggez::conf::FullscreenType::Windowed,
 		borderless: false,
@@ -344,11 +876,41 @@ fn main() -> GameResult {
 		modules: ggez::conf::ModuleConf::default(),
 	};
 
-	let cb = ggez::ContextBuilder::new("super_simple", "ggez").conf(conf);
+	let cb = ggez::ContextBuilder::new("tetris", "MetalElitist").conf(conf);
 	let (ref mut ctx, event_loop) = &mut cb.build()?;
-	let state = &mut MainState::new(ctx)?;
-	for x in 0..grid_cols - 1 {
-		state.grid[x][grid_rows-1] = 1;
+	let settings = Settings::load(ctx).unwrap_or_else(|e| {
+		println!("settings: {}", e);
+		Settings::new()
+	});
+	let board_setup = BoardSetup::from_args(ctx)?;
+	board_setup.check(&board)?;
+	let mode = match mode {
+		Mode::Versus(_) => Mode::Versus(settings.attack),
+		mode => mode,
+	};
+	let state = &mut MainState::new(ctx, settings, board, layout, board_setup, mode, &arg_value("--theme").unwrap_or_else(|| String::from("classic")))?;
+	if let Some(replay) = replay {
+		state.view_replay(replay);
+	} else if let Some(port) = arg_value("--host") {
+		let listener = net::listen(&format!("0.0.0.0:{}", port))?;
+		println!("waiting for a player on port {}", port);
+		let replay = Replay::new(new_seed(), &state.players[0].game.board, state.settings.handling, state.board_setup.clone(), Mode::Versus(state.settings.attack));
+		let (connection, handling) = net::accept(&listener, &replay)?;
+		let replays = net::match_replays(&replay, handling);
+		// Spectators join on the same port once the match is on
+		state.broadcast = Some(Broadcast::new(listener, replays.clone())?);
+		state.start_net(ctx, Some(NetMatch::new(connection, 0)), replays)?;
+	} else if let Some(address) = arg_value("--connect") {
+		let (connection, replay) = net::connect(address.as_str(), state.settings.handling)?;
+		state.start_net(ctx, Some(NetMatch::new(connection, 1)), net::match_replays(&replay, state.settings.handling))?;
+	} else if let Some(address) = arg_value("--watch") {
+		let (spectator, replays) = spectate::watch(address.as_str())?;
+		state.spectator = Some(spectator);
+		state.start_net(ctx, None, replays)?;
+	} else if let Mode::Puzzle(_) = state.mode {
+		let pack = arg_value("--pack").unwrap_or_else(|| String::from("basics"));
+		let puzzles = puzzle::load_pack(ctx, &pack)?;
+		state.open_puzzles(PuzzleSelectScreen::new(ctx, puzzles));
 	}
 
 	event::run(ctx, event_loop, state)
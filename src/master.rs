@@ -0,0 +1,140 @@
+// Master mode rules: the 0 to 999 level counter, its gravity and timing
+// tables and the grades awarded for score.
+
+use crate::game::TICKS_PER_SECOND;
+
+pub const MAX_LEVEL: u16 = 999;
+pub const GRADES: [&str; 19] = ["9", "8", "7", "6", "5", "4", "3", "2", "1", "S1", "S2", "S3", "S4", "S5", "S6", "S7", "S8", "S9", "GM"];
+// Score needed for each grade up to S9, GM has its own conditions
+const GRADE_SCORES: [u32; 18] = [0, 400, 800, 1400, 2000, 3500, 5500, 8000, 12000, 16000, 22000, 30000, 40000, 52000, 66000, 82000, 100000, 120000];
+const S4: usize = 12;
+const S5: usize = 13;
+const S9: usize = 17;
+
+// (from level, rows per tick in 1/256ths), 20G from level 500
+const GRAVITY: [(u16, u32); 31] = [
+	(0, 4), (30, 6), (35, 8), (40, 10), (50, 12), (60, 16), (70, 32), (80, 48), (90, 64), (100, 80),
+	(120, 96), (140, 112), (160, 128), (170, 144), (200, 4), (220, 32), (230, 64), (233, 96), (236, 128), (239, 160),
+	(243, 192), (247, 224), (251, 256), (300, 512), (330, 768), (360, 1024), (370, 1280), (400, 1024), (420, 768), (450, 512),
+	(500, 20 * 256),
+];
+
+// Delays in ticks for a section of levels
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Timings {
+	pub are: u32, // before the next piece enters
+	pub line_are: u32, // the same after a line clear, clear delay included
+	pub das: i32,
+	pub lock: u32, // a piece resting on the stack locks after this long
+}
+
+// (from level, timings)
+const TIMINGS: [(u16, Timings); 6] = [
+	(0, Timings { are: 27, line_are: 67, das: 16, lock: 30 }),
+	(500, Timings { are: 27, line_are: 52, das: 10, lock: 30 }),
+	(600, Timings { are: 27, line_are: 34, das: 10, lock: 30 }),
+	(700, Timings { are: 18, line_are: 26, das: 10, lock: 30 }),
+	(800, Timings { are: 14, line_are: 14, das: 10, lock: 30 }),
+	(900, Timings { are: 14, line_are: 14, das: 8, lock: 17 }),
+];
+
+pub fn gravity(level: u16) -> u32 {
+	GRAVITY.iter().rev().find(|(from, _)| level >= *from).map(|(_, gravity)| *gravity).unwrap_or(4)
+}
+
+pub fn timings(level: u16) -> Timings {
+	TIMINGS.iter().rev().find(|(from, _)| level >= *from).map(|(_, timings)| *timings).unwrap_or(TIMINGS[0].1)
+}
+
+// Pieces don't raise the level past x99 or 998, only a line clear does
+pub fn section_stop(level: u16) -> bool {
+	level % 100 == 99 || level == MAX_LEVEL - 1
+}
+
+// Level the counter stops at next
+pub fn next_stop(level: u16) -> u16 {
+	(level / 100 * 100 + 99).min(MAX_LEVEL)
+}
+
+// Index into GRADES for a score, S9 at most
+pub fn grade(score: u32) -> usize {
+	GRADE_SCORES.iter().rposition(|needed| score >= *needed).unwrap_or(0)
+}
+
+pub fn next_grade_score(grade: usize) -> Option<u32> {
+	GRADE_SCORES.get(grade + 1).cloned()
+}
+
+// Tick and grade when each 100 levels were passed
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Checkpoint {
+	pub tick: u64,
+	pub grade: usize,
+}
+
+// GM takes S9 at level 999 in under 13:30, with S4 by level 300 in 4:15 and S5 by level 500 in 7:00
+pub fn final_grade(score: u32, tick: u64, checkpoints: &[Checkpoint]) -> usize {
+	let grade = grade(score);
+	let by = |section: usize, needed: usize, seconds: u64| {
+		checkpoints.get(section - 1).is_some_and(|c| c.grade >= needed && c.tick <= seconds * TICKS_PER_SECOND as u64)
+	};
+	if grade == S9 && tick <= 810 * TICKS_PER_SECOND as u64 && by(3, S4, 255) && by(5, S5, 420) {
+		return GRADES.len() - 1
+	}
+	grade
+}
+
+// TGM line clear score: lines times the size bonus times the combo, four times as much for clearing the board
+pub fn clear_score(level: u16, lines: u32, soft_rows: u32, combo: u32, bravo: bool) -> u32 {
+	let points = ((level as u32 + lines).div_ceil(4) + soft_rows) * lines * (2 * lines - 1) * combo;
+	if bravo {
+		points * 4
+	} else {
+		points
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn gravity_reaches_20g_at_500() {
+		assert_eq!(gravity(0), 4);
+		assert_eq!(gravity(200), 4);
+		assert_eq!(gravity(499), 512);
+		assert_eq!(gravity(500), 20 * 256);
+		assert_eq!(gravity(999), 20 * 256);
+	}
+
+	#[test]
+	fn timings_tighten_by_section() {
+		assert_eq!(timings(499).das, 16);
+		assert_eq!(timings(500).das, 10);
+		assert_eq!((timings(950).das, timings(950).lock), (8, 17));
+	}
+
+	#[test]
+	fn levels_stop_before_sections() {
+		assert!(section_stop(99) && section_stop(998) && !section_stop(100));
+		assert_eq!((next_stop(0), next_stop(150), next_stop(950)), (99, 199, 999));
+	}
+
+	#[test]
+	fn grades_follow_score() {
+		assert_eq!(GRADES[grade(0)], "9");
+		assert_eq!(GRADES[grade(16000)], "S1");
+		assert_eq!(GRADES[grade(1_000_000)], "S9");
+		assert_eq!(next_grade_score(grade(1_000_000)), None);
+	}
+
+	#[test]
+	fn gm_needs_the_checkpoints() {
+		let fast = |grade| Checkpoint { tick: 60, grade };
+		let checkpoints = [fast(0), fast(5), fast(S4), fast(S4), fast(S5)];
+		assert_eq!(GRADES[final_grade(130000, 600 * 60, &checkpoints)], "GM");
+		assert_eq!(GRADES[final_grade(130000, 900 * 60, &checkpoints)], "S9");
+		let slow = [fast(0), fast(5), fast(S4), fast(S4), Checkpoint { tick: 500 * 60, grade: S5 }];
+		assert_eq!(GRADES[final_grade(130000, 600 * 60, &slow)], "S9");
+	}
+}
@@ -0,0 +1,247 @@
+// Game modes: what ends a game and how it is scored
+
+use crate::game::TICKS_PER_SECOND;
+use crate::master::{self, Timings};
+use crate::puzzle::PuzzleGoal;
+use crate::versus::AttackTable;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Mode {
+	Marathon { goal: MarathonGoal, endless: bool, variable: bool }, // play on after the goal if endless
+	Sprint { lines: u16 }, // clear this many lines as fast as possible
+	Ultra { seconds: u16 }, // score as much as possible before the time runs out
+	Dig { rows: u16, messy: bool }, // clear all the garbage rows the game starts with
+	Survival, // garbage rises from the bottom faster and faster until topping out
+	Master, // levels 0 to 999 with 20G from 500 and a grade for the score
+	Invisible { lines: u16, fade: u16, outline: bool }, // clear lines while locked blocks fade out over `fade` seconds
+	Puzzle(PuzzleGoal), // a fixed piece sequence on a prepared board
+	Versus(AttackTable), // two players, line clears send garbage to the other one
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MarathonGoal {
+	Lines(u16),
+	Level(u16), // win by completing this level
+}
+
+pub const MARATHON_LINES: [u16; 2] = [150, 200];
+pub const SPRINT_LINES: [u16; 3] = [20, 40, 100];
+pub const SPLIT_LINES: u16 = 10; // sprint splits are taken every this many lines
+
+// Guideline gravity in 1/256 rows per tick for levels 1 to 20, capped at 20G
+const GRAVITY: [u32; 20] = [4, 5, 7, 9, 12, 16, 22, 32, 45, 67, 99, 151, 235, 373, 604, 1001, 1693, 2928, 5120, 5120];
+// Variable goals award more lines for bigger clears
+const VARIABLE_AWARDS: [u16; 5] = [0, 1, 3, 5, 8];
+
+impl Mode {
+	// --mode marathon|sprint|ultra|dig|survival|master|invisible|puzzle|versus. Marathon takes --lines 150|200 or --level 15 as the goal,
+	// --endless to keep playing after it and --variable for variable level goals.
+	// --lines picks the sprint goal and --time the ultra length in seconds.
+	// Dig takes the number of garbage --rows and --holes clean|messy.
+	// Invisible takes --lines to clear, --fade <seconds> (0 hides blocks as they lock)
+	// and --outline to show the stack's outline after a line clear.
+	// Puzzles come from a --pack, the goal is replaced by the one of the puzzle being played.
+	// Versus uses the attack table from the settings file.
+	pub fn from_args() -> Self {
+		match crate::arg_value("--mode").as_deref() {
+			Some("sprint") => {
+				let lines = crate::arg_value("--lines").and_then(|lines| lines.parse().ok()).unwrap_or(40);
+				if !SPRINT_LINES.contains(&lines) {
+					eprintln!("sprint: {} lines is not one of {:?}, using 40", lines, SPRINT_LINES);
+					return Mode::Sprint { lines: 40 }
+				}
+				Mode::Sprint { lines }
+			},
+			Some("ultra") => {
+				let seconds = crate::arg_value("--time").and_then(|seconds| seconds.parse().ok()).unwrap_or(120);
+				Mode::Ultra { seconds: seconds.max(1) }
+			},
+			Some("dig") => {
+				let rows = crate::arg_value("--rows").and_then(|rows| rows.parse().ok()).unwrap_or(10);
+				let messy = match crate::arg_value("--holes").as_deref() {
+					Some("clean") => false,
+					Some("messy") | None => true,
+					Some(holes) => {
+						eprintln!("dig: unknown hole style {}, using messy", holes);
+						true
+					},
+				};
+				Mode::Dig { rows: rows.max(1), messy }
+			},
+			Some("survival") => Mode::Survival,
+			Some("master") => Mode::Master,
+			Some("puzzle") => Mode::Puzzle(PuzzleGoal::Lines(1)),
+			Some("versus") => Mode::Versus(AttackTable::new()),
+			Some("invisible") => Mode::Invisible {
+				lines: crate::arg_value("--lines").and_then(|lines| lines.parse().ok()).unwrap_or(40).max(1),
+				fade: crate::arg_value("--fade").and_then(|fade| fade.parse().ok()).unwrap_or(0),
+				outline: crate::arg_flag("--outline"),
+			},
+			Some("marathon") | None => Mode::marathon_from_args(),
+			Some(mode) => {
+				eprintln!("unknown mode {}, playing marathon", mode);
+				Mode::marathon_from_args()
+			},
+		}
+	}
+
+	fn marathon_from_args() -> Self {
+		let goal = match (crate::arg_value("--lines"), crate::arg_value("--level")) {
+			(_, Some(level)) => MarathonGoal::Level(level.parse().unwrap_or(15).max(1)),
+			(Some(lines), None) => {
+				let lines = lines.parse().unwrap_or(150);
+				if !MARATHON_LINES.contains(&lines) {
+					eprintln!("marathon: {} lines is not one of {:?}, using 150", lines, MARATHON_LINES);
+					MarathonGoal::Lines(150)
+				} else {
+					MarathonGoal::Lines(lines)
+				}
+			},
+			(None, None) => MarathonGoal::Lines(150),
+		};
+		Mode::Marathon { goal, endless: crate::arg_flag("--endless"), variable: crate::arg_flag("--variable") }
+	}
+
+	pub fn marathon() -> Self {
+		Mode::Marathon { goal: MarathonGoal::Lines(150), endless: false, variable: false }
+	}
+
+	// Rows per tick in 1/256ths at a level, starting from 1
+	pub fn gravity(&self, level: u16) -> u32 {
+		if *self == Mode::Master {
+			return master::gravity(level)
+		}
+		GRAVITY[(level.max(1) as usize - 1).min(GRAVITY.len() - 1)]
+	}
+
+	// Entry delay, DAS and lock delay, only master has them. Other modes
+	// spawn the next piece right away and lock a piece when it can't fall.
+	pub fn timings(&self, level: u16) -> Option<Timings> {
+		match self {
+			Mode::Master => Some(master::timings(level)),
+			_ => None,
+		}
+	}
+
+	// Lines needed to complete a level
+	pub fn level_goal(&self, level: u16) -> u16 {
+		match self {
+			Mode::Marathon { variable: true, .. } => 5 * level,
+			_ => 10,
+		}
+	}
+
+	// Lines a clear counts for towards the level goal
+	pub fn awarded_lines(&self, cleared: u32) -> u16 {
+		match self {
+			Mode::Marathon { variable: true, .. } => VARIABLE_AWARDS[cleared.min(4) as usize],
+			_ => cleared as u16,
+		}
+	}
+
+	pub fn name(&self) -> String {
+		match self {
+			Mode::Marathon { goal, endless, variable } => {
+				let mut name = match goal {
+					MarathonGoal::Lines(lines) => format!("marathon {}", lines),
+					MarathonGoal::Level(level) => format!("marathon level {}", level),
+				};
+				if *variable {
+					name += " variable";
+				}
+				if *endless {
+					name += " endless";
+				}
+				name
+			},
+			Mode::Sprint { lines } => format!("sprint {}", lines),
+			Mode::Ultra { seconds } => format!("ultra {}", seconds),
+			Mode::Survival => String::from("survival"),
+			Mode::Master => String::from("master"),
+			Mode::Puzzle(_) => String::from("puzzle"),
+			Mode::Versus(_) => String::from("versus"),
+			Mode::Invisible { lines, fade, outline } => {
+				let mut name = match fade {
+					0 => format!("invisible {}", lines),
+					fade => format!("fading {} {}s", lines, fade),
+				};
+				if *outline {
+					name += " outline";
+				}
+				name
+			},
+			Mode::Dig { rows, messy } => format!("dig {} {}", rows, if *messy { "messy" } else { "clean" }),
+		}
+	}
+}
+
+// Ticks between garbage rows in survival: 8 seconds at first, each row comes
+// 5% sooner than the one before, reaching one row a second after 38 rows
+pub fn survival_interval(rises: u32) -> u64 {
+	let fastest = TICKS_PER_SECOND as u64;
+	let mut interval = 8 * fastest;
+	for _ in 0..rises {
+		if interval <= fastest {
+			break;
+		}
+		interval = interval * 19 / 20;
+	}
+	interval.max(fastest)
+}
+
+// Ticks left before the game ends, for modes with a time limit
+pub fn time_left(mode: Mode, tick: u64) -> Option<u64> {
+	match mode {
+		Mode::Ultra { seconds } => Some((seconds as u64 * TICKS_PER_SECOND as u64).saturating_sub(tick)),
+		_ => None,
+	}
+}
+
+// m:ss.mmm from the tick clock
+pub fn format_time(ticks: u64) -> String {
+	let ms = ticks * 1000 / TICKS_PER_SECOND as u64;
+	format!("{}:{:02}.{:03}", ms / 60000, ms / 1000 % 60, ms % 1000)
+}
+
+// +s.mmm when behind, -s.mmm when ahead
+pub fn format_delta(ticks: u64, best: u64) -> String {
+	let (sign, diff) = if ticks >= best { ('+', ticks - best) } else { ('-', best - ticks) };
+	let ms = diff * 1000 / TICKS_PER_SECOND as u64;
+	format!("{}{}.{:03}", sign, ms / 1000, ms % 1000)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn fixed_goals_are_ten_lines() {
+		let mode = Mode::marathon();
+		assert_eq!((mode.level_goal(1), mode.level_goal(14)), (10, 10));
+		assert_eq!(mode.awarded_lines(4), 4);
+	}
+
+	#[test]
+	fn variable_goals_grow_and_reward_big_clears() {
+		let mode = Mode::Marathon { goal: MarathonGoal::Level(15), endless: false, variable: true };
+		assert_eq!((mode.level_goal(1), mode.level_goal(3)), (5, 15));
+		assert_eq!((mode.awarded_lines(1), mode.awarded_lines(4)), (1, 8));
+	}
+
+	#[test]
+	fn gravity_stops_at_20g() {
+		let mode = Mode::marathon();
+		assert_eq!(mode.gravity(1), 4);
+		assert_eq!(mode.gravity(19), 20 * 256);
+		assert_eq!(mode.gravity(40), 20 * 256);
+	}
+
+	#[test]
+	fn survival_rows_come_sooner_and_sooner() {
+		assert_eq!(survival_interval(0), 480);
+		assert_eq!(survival_interval(1), 456);
+		assert_eq!(survival_interval(10), 285);
+		assert_eq!(survival_interval(20), 167);
+		assert_eq!((survival_interval(37), survival_interval(38), survival_interval(1000)), (64, 60, 60));
+	}
+}
@@ -0,0 +1,641 @@
+// Online versus: two instances play the same match in lockstep over TCP.
+// Both sides simulate both games and only inputs are sent. Every tick a side
+// sends what its player pressed, stamped INPUT_DELAY ticks ahead so that it
+// usually arrives before it is needed, and a tick is only played once the
+// inputs of both sides for it are in.
+//
+// Messages are framed as a u16 length followed by the payload, whose first byte is the type:
+//   0 = hello (protocol version u8, handling: delay u16, repeat u16, fallback_keeps_charge u8), client to host
+//   1 = welcome (protocol version u8, then a replay without inputs: seed, board, the host's handling, setup and mode), host to client
+//   2 = reject (reason as UTF-8), host to client
+//   3 = inputs (tick u64, inputs: count u8 and each input as the byte replays use)
+//   4 = watch (protocol version u8), spectator to host
+//   5 = match (protocol version u8, then both players' replays without inputs), host to spectator
+//   6 = frames (number of the first frame u64, frame count u16, then per frame the inputs of both players), host to spectators
+//   7 = snapshot (frame u64, board count u8, the boards as Board::write puts them), host to spectator
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+use crate::board::Board;
+use crate::das::Handling;
+use crate::game::{Game, TICKS_PER_SECOND};
+use crate::input::GameAction;
+use crate::replay::Replay;
+use crate::versus;
+
+pub const PROTOCOL_VERSION: u8 = 4;
+// Ticks between a key press and the tick it is played on
+pub const INPUT_DELAY: u64 = 3;
+// Ticks before the games start, counted in lockstep so both sides start together
+pub const COUNTDOWN: u64 = 3 * TICKS_PER_SECOND as u64;
+// A side that sends nothing for this long has gone
+const TIMEOUT: Duration = Duration::from_secs(5);
+// Bytes waiting to be written before a side that doesn't read is given up on
+const MAX_UNSENT: usize = 1 << 20;
+
+// Inputs of both players played on one frame
+pub type FrameInputs = [Vec<(GameAction, bool)>; 2];
+
+pub enum Message {
+	Hello { version: u8, handling: Handling },
+	Welcome { version: u8, replay: Replay },
+	Reject(String),
+	Inputs { tick: u64, inputs: Vec<(GameAction, bool)> },
+	Watch { version: u8 },
+	Match { version: u8, replays: [Replay; 2] },
+	Frames { frame: u64, frames: Vec<FrameInputs> },
+	Snapshot { frame: u64, boards: Vec<Board> },
+}
+
+impl Message {
+	fn write(&self, w: &mut Vec<u8>) -> io::Result<()> {
+		match self {
+			Message::Hello { version, handling } => {
+				w.extend_from_slice(&[0, *version]);
+				w.extend_from_slice(&(handling.delay as u16).to_le_bytes());
+				w.extend_from_slice(&(handling.repeat as u16).to_le_bytes());
+				w.push(handling.fallback_keeps_charge as u8);
+			},
+			Message::Welcome { version, replay } => {
+				w.extend_from_slice(&[1, *version]);
+				replay.write(w)?;
+			},
+			Message::Reject(reason) => {
+				w.push(2);
+				w.extend_from_slice(reason.as_bytes());
+			},
+			Message::Inputs { tick, inputs } => {
+				w.push(3);
+				w.extend_from_slice(&tick.to_le_bytes());
+				write_inputs(w, inputs);
+			},
+			Message::Watch { version } => w.extend_from_slice(&[4, *version]),
+			Message::Match { version, replays } => {
+				w.extend_from_slice(&[5, *version]);
+				for replay in replays {
+					replay.write(w)?;
+				}
+			},
+			Message::Frames { frame, frames } => {
+				w.push(6);
+				w.extend_from_slice(&frame.to_le_bytes());
+				w.extend_from_slice(&(frames.len() as u16).to_le_bytes());
+				for inputs in frames.iter().flatten() {
+					write_inputs(w, inputs);
+				}
+			},
+			Message::Snapshot { frame, boards } => {
+				w.push(7);
+				w.extend_from_slice(&frame.to_le_bytes());
+				w.push(boards.len() as u8);
+				for board in boards {
+					board.write(w)?;
+				}
+			},
+		}
+		Ok(())
+	}
+
+	// Whether the message is short enough for a frame
+	pub fn fits(&self) -> bool {
+		let mut payload = Vec::new();
+		self.write(&mut payload).is_ok() && payload.len() <= u16::MAX as usize
+	}
+
+	fn read(payload: &[u8]) -> io::Result<Message> {
+		let (kind, mut r) = payload.split_first().ok_or_else(|| invalid("empty message"))?;
+		match kind {
+			0 => {
+				let mut bytes = [0; 6];
+				r.read_exact(&mut bytes)?;
+				let handling = Handling {
+					delay: u16::from_le_bytes([bytes[1], bytes[2]]) as i32,
+					repeat: u16::from_le_bytes([bytes[3], bytes[4]]) as i32,
+					fallback_keeps_charge: bytes[5] != 0,
+				};
+				Ok(Message::Hello { version: bytes[0], handling })
+			},
+			1 => {
+				let version = *r.first().ok_or_else(|| invalid("short welcome"))?;
+				Ok(Message::Welcome { version, replay: Replay::read(&mut &r[1..])? })
+			},
+			2 => Ok(Message::Reject(String::from_utf8_lossy(r).into_owned())),
+			3 => {
+				let mut tick = [0; 8];
+				r.read_exact(&mut tick)?;
+				Ok(Message::Inputs { tick: u64::from_le_bytes(tick), inputs: read_inputs(&mut r)? })
+			},
+			4 => r.first().map(|version| Message::Watch { version: *version }).ok_or_else(|| invalid("short watch")),
+			5 => {
+				let (version, mut r) = r.split_first().ok_or_else(|| invalid("short match"))?;
+				Ok(Message::Match { version: *version, replays: [Replay::read(&mut r)?, Replay::read(&mut r)?] })
+			},
+			6 => {
+				let mut frame = [0; 8];
+				r.read_exact(&mut frame)?;
+				let mut count = [0; 2];
+				r.read_exact(&mut count)?;
+				let mut frames = Vec::new();
+				for _ in 0..u16::from_le_bytes(count) {
+					frames.push([read_inputs(&mut r)?, read_inputs(&mut r)?]);
+				}
+				Ok(Message::Frames { frame: u64::from_le_bytes(frame), frames })
+			},
+			7 => {
+				let mut frame = [0; 8];
+				r.read_exact(&mut frame)?;
+				let mut count = [0];
+				r.read_exact(&mut count)?;
+				let mut boards = Vec::new();
+				for _ in 0..count[0] {
+					boards.push(Board::read(&mut r)?);
+				}
+				Ok(Message::Snapshot { frame: u64::from_le_bytes(frame), boards })
+			},
+			_ => Err(invalid("unknown message")),
+		}
+	}
+}
+
+fn write_inputs(w: &mut Vec<u8>, inputs: &[(GameAction, bool)]) {
+	w.push(inputs.len() as u8);
+	for (action, pressed) in inputs {
+		let index = GameAction::ALL.iter().position(|a| a == action).unwrap() as u8;
+		w.push(index << 1 | *pressed as u8);
+	}
+}
+
+fn read_inputs(r: &mut &[u8]) -> io::Result<Vec<(GameAction, bool)>> {
+	let mut count = [0];
+	r.read_exact(&mut count)?;
+	let mut inputs = Vec::new();
+	for _ in 0..count[0] {
+		let mut byte = [0];
+		r.read_exact(&mut byte)?;
+		let action = *GameAction::ALL.get((byte[0] >> 1) as usize).ok_or_else(|| invalid("bad input"))?;
+		inputs.push((action, byte[0] & 1 == 1));
+	}
+	Ok(inputs)
+}
+
+pub struct Connection {
+	stream: TcpStream,
+	received: Vec<u8>, // read bytes that don't make a whole frame yet
+	unsent: Vec<u8>, // frames the socket hasn't taken yet
+	last_heard: Instant,
+}
+
+impl Connection {
+	pub fn new(stream: TcpStream) -> io::Result<Connection> {
+		stream.set_nodelay(true)?;
+		stream.set_read_timeout(Some(TIMEOUT))?;
+		Ok(Connection { stream, received: Vec::new(), unsent: Vec::new(), last_heard: Instant::now() })
+	}
+
+	pub fn send(&mut self, message: &Message) -> io::Result<()> {
+		let mut payload = Vec::new();
+		message.write(&mut payload)?;
+		if payload.len() > u16::MAX as usize {
+			return Err(invalid("message too long"))
+		}
+		if self.unsent.len() + 2 + payload.len() > MAX_UNSENT {
+			return Err(io::Error::new(io::ErrorKind::TimedOut, "the other side stopped reading"))
+		}
+		self.unsent.extend_from_slice(&(payload.len() as u16).to_le_bytes());
+		self.unsent.extend_from_slice(&payload);
+		self.flush()
+	}
+
+	// Writes as much as the socket takes without blocking, the rest goes with a later send or flush
+	pub fn flush(&mut self) -> io::Result<()> {
+		while !self.unsent.is_empty() {
+			match self.stream.write(&self.unsent) {
+				Ok(0) => return Err(io::Error::new(io::ErrorKind::WriteZero, "connection closed")),
+				Ok(n) => {
+					self.unsent.drain(..n);
+				},
+				Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+				Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+				Err(e) => return Err(e),
+			}
+		}
+		Ok(())
+	}
+
+	// The next whole message, None if it hasn't all arrived yet. Only waits
+	// for it during the handshake, the match reads without blocking.
+	pub fn receive(&mut self) -> io::Result<Option<Message>> {
+		let mut buf = [0; 4096];
+		while self.frame_len().is_none() {
+			match self.stream.read(&mut buf) {
+				Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed")),
+				Ok(n) => {
+					self.received.extend_from_slice(&buf[..n]);
+					self.last_heard = Instant::now();
+				},
+				Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+					if self.last_heard.elapsed() > TIMEOUT {
+						return Err(io::Error::new(io::ErrorKind::TimedOut, "connection timed out"))
+					}
+					return Ok(None)
+				},
+				Err(e) => return Err(e),
+			}
+		}
+		let len = self.frame_len().unwrap();
+		let message = Message::read(&self.received[2..len]);
+		self.received.drain(..len);
+		message.map(Some)
+	}
+
+	// Length of the first frame with its header, if all of it has been read
+	fn frame_len(&self) -> Option<usize> {
+		if self.received.len() < 2 {
+			return None
+		}
+		let len = 2 + u16::from_le_bytes([self.received[0], self.received[1]]) as usize;
+		if self.received.len() >= len {
+			Some(len)
+		} else {
+			None
+		}
+	}
+
+	// Handshake messages are waited for, a missing one times out
+	pub fn wait(&mut self) -> io::Result<Message> {
+		loop {
+			if let Some(message) = self.receive()? {
+				return Ok(message)
+			}
+		}
+	}
+
+	// Once the match is on, reads don't block the game
+	pub fn start(&mut self) -> io::Result<()> {
+		self.stream.set_nonblocking(true)
+	}
+}
+
+pub fn listen(address: &str) -> io::Result<TcpListener> {
+	TcpListener::bind(address)
+}
+
+// Waits for a player to connect and sends them the match. Returns the
+// connection and the handling the other player plays with. Clients that
+// can't play, and spectators that come before the match has started, are
+// turned away and the host keeps waiting.
+pub fn accept(listener: &TcpListener, replay: &Replay) -> io::Result<(Connection, Handling)> {
+	loop {
+		let (stream, address) = listener.accept()?;
+		match greet(stream, replay) {
+			Ok(player) => return Ok(player),
+			Err(e) => eprintln!("turned away {}: {}", address, e),
+		}
+	}
+}
+
+// The handshake with one client, which is rejected unless it is a player of the same version
+fn greet(stream: TcpStream, replay: &Replay) -> io::Result<(Connection, Handling)> {
+	let mut connection = Connection::new(stream)?;
+	let reason = match connection.wait()? {
+		Message::Hello { version, handling } if version == PROTOCOL_VERSION => {
+			connection.send(&Message::Welcome { version: PROTOCOL_VERSION, replay: replay.clone() })?;
+			connection.start()?;
+			return Ok((connection, handling))
+		},
+		Message::Hello { version, .. } => version_mismatch(version),
+		Message::Watch { .. } => String::from("the match hasn't started yet"),
+		_ => String::from("expected hello"),
+	};
+	// Nothing to do if the client has gone already
+	let _ = connection.send(&Message::Reject(reason.clone()));
+	Err(invalid(&reason))
+}
+
+pub fn version_mismatch(version: u8) -> String {
+	format!("protocol version {} doesn't match the host's {}", version, PROTOCOL_VERSION)
+}
+
+// Joins a hosted match, returns the connection and the match as a replay without inputs
+pub fn connect<A: ToSocketAddrs>(address: A, handling: Handling) -> io::Result<(Connection, Replay)> {
+	let mut connection = Connection::new(TcpStream::connect(address)?)?;
+	connection.send(&Message::Hello { version: PROTOCOL_VERSION, handling })?;
+	match connection.wait()? {
+		Message::Welcome { version, replay } if version == PROTOCOL_VERSION => {
+			connection.start()?;
+			Ok((connection, replay))
+		},
+		Message::Welcome { version, .. } => Err(invalid(&format!("the host runs protocol version {}, this build {}", version, PROTOCOL_VERSION))),
+		Message::Reject(reason) => Err(io::Error::new(io::ErrorKind::ConnectionRefused, reason)),
+		_ => Err(invalid("expected welcome")),
+	}
+}
+
+// The two replays a match starts from: the host is player 1 and the client
+// player 2, each with their own handling
+pub fn match_replays(replay: &Replay, client_handling: Handling) -> [Replay; 2] {
+	let mut client = replay.clone();
+	client.handling = client_handling;
+	[replay.clone(), client]
+}
+
+// A match in progress. This side's inputs go out, the opponent's come in and
+// both games advance together once a tick's inputs from both are known.
+pub struct NetMatch {
+	pub local: usize, // the player on this side, 0 for the host
+	pub frame: u64, // ticks played in lockstep, the countdown included
+	pub disconnected: Option<String>,
+	connection: Connection,
+	pressed: Vec<(GameAction, bool)>, // inputs since the last frame was sent
+	sent: u64, // frames sent so far
+	received: u64,
+	frames: [VecDeque<Vec<(GameAction, bool)>>; 2], // inputs of the frames to come by player
+}
+
+impl NetMatch {
+	pub fn new(connection: Connection, local: usize) -> Self {
+		NetMatch {
+			local,
+			frame: 0,
+			disconnected: None,
+			connection,
+			pressed: Vec::new(),
+			sent: 0,
+			received: 0,
+			frames: [VecDeque::new(), VecDeque::new()],
+		}
+	}
+
+	// Presses during the countdown are dropped, releases always go through
+	pub fn input(&mut self, action: GameAction, pressed: bool) {
+		if self.frame + INPUT_DELAY >= COUNTDOWN || !pressed {
+			self.pressed.push((action, pressed));
+		}
+	}
+
+	pub fn countdown_left(&self) -> u64 {
+		COUNTDOWN.saturating_sub(self.frame)
+	}
+
+	// Called every tick: sends this side's inputs, reads the opponent's and
+	// plays the frames that have both. A side that fell behind catches up two
+	// frames a tick. Returns the inputs of the frames played.
+	pub fn tick(&mut self, first: &mut Game, second: &mut Game) -> Vec<FrameInputs> {
+		let mut played = Vec::new();
+		if self.disconnected.is_some() {
+			return played
+		}
+		if let Err(e) = self.exchange() {
+			self.disconnected = Some(e.to_string());
+			return played
+		}
+		for _ in 0..2 {
+			if self.frames.iter().any(|frames| frames.is_empty()) {
+				break;
+			}
+			let inputs = [self.frames[0].pop_front().unwrap(), self.frames[1].pop_front().unwrap()];
+			play_frame(self.frame, &inputs, first, second);
+			self.frame += 1;
+			played.push(inputs);
+		}
+		played
+	}
+
+	fn exchange(&mut self) -> io::Result<()> {
+		self.connection.flush()?;
+		while self.sent <= self.frame + INPUT_DELAY {
+			let inputs = std::mem::take(&mut self.pressed);
+			self.connection.send(&Message::Inputs { tick: self.sent, inputs: inputs.clone() })?;
+			self.frames[self.local].push_back(inputs);
+			self.sent += 1;
+		}
+		while let Some(message) = self.connection.receive()? {
+			match message {
+				Message::Inputs { tick, inputs } if tick == self.received => {
+					self.frames[1 - self.local].push_back(inputs);
+					self.received += 1;
+				},
+				_ => return Err(invalid("unexpected message")),
+			}
+		}
+		Ok(())
+	}
+}
+
+// Plays one lockstep frame of a match, the countdown frames only count
+pub fn play_frame(frame: u64, inputs: &FrameInputs, first: &mut Game, second: &mut Game) {
+	if frame < COUNTDOWN {
+		return
+	}
+	for (action, pressed) in inputs[0].iter() {
+		first.action(*action, *pressed);
+	}
+	for (action, pressed) in inputs[1].iter() {
+		second.action(*action, *pressed);
+	}
+	versus::settle(first, second);
+	first.tick();
+	second.tick();
+	versus::settle(first, second);
+}
+
+pub fn invalid(message: &str) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::thread;
+	use crate::board::Board;
+	use crate::board_setup::BoardSetup;
+	use crate::mode::Mode;
+	use crate::versus::AttackTable;
+
+	fn replay() -> Replay {
+		Replay::new(11, &Board::new(10, 20, 20), Handling::new(), BoardSetup::Empty, Mode::Versus(AttackTable::new()))
+	}
+
+	// Host and client connected over loopback, the client with a faster handling
+	fn connected() -> (NetMatch, NetMatch, [Replay; 2]) {
+		let listener = listen("127.0.0.1:0").unwrap();
+		let port = listener.local_addr().unwrap().port();
+		let handling = Handling { delay: 8, ..Handling::new() };
+		let client = thread::spawn(move || connect(("127.0.0.1", port), handling).unwrap());
+		let (host, client_handling) = accept(&listener, &replay()).unwrap();
+		let (client, welcome) = client.join().unwrap();
+		assert_eq!((welcome.seed, welcome.mode, client_handling.delay), (11, Mode::Versus(AttackTable::new()), 8));
+		(NetMatch::new(host, 0), NetMatch::new(client, 1), match_replays(&welcome, client_handling))
+	}
+
+	#[test]
+	fn inputs_survive_framing() {
+		let message = Message::Inputs { tick: 300, inputs: vec![(GameAction::HardDrop, true), (GameAction::MoveLeft, false)] };
+		let mut payload = Vec::new();
+		message.write(&mut payload).unwrap();
+		match Message::read(&payload).unwrap() {
+			Message::Inputs { tick, inputs } => assert_eq!((tick, inputs), (300, vec![(GameAction::HardDrop, true), (GameAction::MoveLeft, false)])),
+			_ => panic!("not inputs"),
+		}
+	}
+
+	#[test]
+	fn spectator_messages_survive_framing() {
+		let frames = vec![[vec![(GameAction::Hold, true)], Vec::new()], [Vec::new(), vec![(GameAction::RotateCCW, false)]]];
+		let mut payload = Vec::new();
+		Message::Frames { frame: 900, frames: frames.clone() }.write(&mut payload).unwrap();
+		match Message::read(&payload).unwrap() {
+			Message::Frames { frame, frames: read } => assert_eq!((frame, read), (900, frames)),
+			_ => panic!("not frames"),
+		}
+		let mut board = Board::new(10, 20, 20);
+		board.set(3, 39, crate::board::Cell::Garbage);
+		let mut payload = Vec::new();
+		Message::Snapshot { frame: 5, boards: vec![board.clone(), Board::new(10, 20, 20)] }.write(&mut payload).unwrap();
+		match Message::read(&payload).unwrap() {
+			Message::Snapshot { frame, boards } => assert_eq!((frame, boards[0].hash(), boards.len()), (5, board.hash(), 2)),
+			_ => panic!("not a snapshot"),
+		}
+	}
+
+	#[test]
+	fn snapshots_keep_sizes_over_255_and_check_their_length() {
+		let mut payload = Vec::new();
+		Message::Snapshot { frame: 0, boards: vec![Board::new(300, 20, 20)] }.write(&mut payload).unwrap();
+		match Message::read(&payload).unwrap() {
+			Message::Snapshot { boards, .. } => assert_eq!((boards[0].width, boards[0].height, boards[0].buffer), (300, 20, 20)),
+			_ => panic!("not a snapshot"),
+		}
+		// Two boards of 300x120 cells don't fit in a frame
+		assert!(!Message::Snapshot { frame: 0, boards: vec![Board::new(300, 60, 60), Board::new(300, 60, 60)] }.fits());
+	}
+
+	#[test]
+	fn boards_too_big_to_play_are_refused() {
+		let mut payload = vec![7];
+		payload.extend_from_slice(&0u64.to_le_bytes());
+		payload.push(1);
+		for size in [65535u16, 65535, 65535] {
+			payload.extend_from_slice(&size.to_le_bytes());
+		}
+		let error = Message::read(&payload).err().unwrap();
+		assert_eq!((error.kind(), error.to_string()), (io::ErrorKind::InvalidData, String::from("board too big")));
+	}
+
+	#[test]
+	fn both_sides_play_the_same_match() {
+		let (mut host, mut client, replays) = connected();
+		let mut host_games = [replays[0].start_game(), replays[1].start_game()];
+		let mut client_games = [replays[0].start_game(), replays[1].start_game()];
+		let end = COUNTDOWN + 120;
+		let (mut dropped, mut moved) = (false, false);
+		for _ in 0..100_000 {
+			if host.frame >= end && client.frame >= end {
+				break;
+			}
+			if host.frame >= COUNTDOWN + 10 && !dropped {
+				host.input(GameAction::HardDrop, true);
+				dropped = true;
+			}
+			if client.frame >= COUNTDOWN + 20 && !moved {
+				client.input(GameAction::MoveLeft, true);
+				moved = true;
+			}
+			// A side stops at the end, it has sent enough inputs for the other one to get there too
+			if host.frame < end {
+				let [first, second] = &mut host_games;
+				host.tick(first, second);
+			}
+			if client.frame < end {
+				let [first, second] = &mut client_games;
+				client.tick(first, second);
+			}
+			thread::sleep(Duration::from_micros(100));
+		}
+		assert!(host.disconnected.is_none() && client.disconnected.is_none());
+		assert_eq!((host_games[0].pieces, client_games[0].pieces), (1, 1));
+		for (host_game, client_game) in host_games.iter().zip(client_games.iter()) {
+			assert_eq!(host_game.board.hash(), client_game.board.hash());
+		}
+		assert!(client_games[1].tetr.pos.x < client_games[0].tetr.pos.x);
+	}
+
+	#[test]
+	fn turned_away_clients_dont_stop_the_host() {
+		let listener = listen("127.0.0.1:0").unwrap();
+		let port = listener.local_addr().unwrap().port();
+		let clients = thread::spawn(move || {
+			// An outdated client, then one that sends the wrong message
+			let mut outdated = Connection::new(TcpStream::connect(("127.0.0.1", port)).unwrap()).unwrap();
+			outdated.send(&Message::Hello { version: PROTOCOL_VERSION + 1, handling: Handling::new() }).unwrap();
+			let rejected = matches!(outdated.wait(), Ok(Message::Reject(_)));
+			let mut stray = Connection::new(TcpStream::connect(("127.0.0.1", port)).unwrap()).unwrap();
+			stray.send(&Message::Inputs { tick: 0, inputs: Vec::new() }).unwrap();
+			let _ = stray.wait();
+			(rejected, connect(("127.0.0.1", port), Handling::new()).is_ok())
+		});
+		assert!(accept(&listener, &replay()).is_ok());
+		assert_eq!(clients.join().unwrap(), (true, true));
+	}
+
+	#[test]
+	fn clients_check_the_host_version() {
+		let listener = listen("127.0.0.1:0").unwrap();
+		let port = listener.local_addr().unwrap().port();
+		let host = thread::spawn(move || {
+			let mut connection = Connection::new(listener.accept().unwrap().0).unwrap();
+			connection.wait().unwrap();
+			connection.send(&Message::Welcome { version: PROTOCOL_VERSION + 1, replay: replay() }).unwrap();
+		});
+		let error = connect(("127.0.0.1", port), Handling::new()).err().unwrap();
+		host.join().unwrap();
+		assert!(error.to_string().contains("protocol version"));
+	}
+
+	#[test]
+	fn sends_wait_for_a_full_socket() {
+		let listener = listen("127.0.0.1:0").unwrap();
+		let port = listener.local_addr().unwrap().port();
+		let mut sender = Connection::new(TcpStream::connect(("127.0.0.1", port)).unwrap()).unwrap();
+		let mut receiver = Connection::new(listener.accept().unwrap().0).unwrap();
+		sender.start().unwrap();
+		receiver.start().unwrap();
+		// More than the socket buffers hold while nobody reads
+		let reason = "x".repeat(60_000);
+		for _ in 0..16 {
+			sender.send(&Message::Reject(reason.clone())).unwrap();
+		}
+		let mut received = 0;
+		for _ in 0..10_000 {
+			sender.flush().unwrap();
+			while let Some(message) = receiver.receive().unwrap() {
+				assert!(matches!(message, Message::Reject(text) if text == reason));
+				received += 1;
+			}
+			if received == 16 {
+				break;
+			}
+			thread::sleep(Duration::from_millis(1));
+		}
+		assert_eq!(received, 16);
+	}
+
+	#[test]
+	fn closing_the_connection_disconnects_the_other_side() {
+		let (mut host, client, replays) = connected();
+		drop(client);
+		let mut games = [replays[0].start_game(), replays[1].start_game()];
+		for _ in 0..1000 {
+			let [first, second] = &mut games;
+			host.tick(first, second);
+			if host.disconnected.is_some() {
+				break;
+			}
+			thread::sleep(Duration::from_millis(1));
+		}
+		assert!(host.disconnected.is_some());
+	}
+}
@@ -0,0 +1,159 @@
+// Puzzles: a starting board, a fixed piece sequence and a goal, loaded in packs
+// from resources/puzzles/<pack>.txt. A pack holds one or more puzzles, each
+// starting with a `name = ` line:
+//
+//   name = First T-spin
+//   pieces = TIO
+//   goal = tspin double
+//   ..........
+//   XX..XXXXXX
+//   XXX.XXXXXX
+//
+// Rows are written the same way as presets. Goals are `lines <n>`,
+// `perfect clear`, `tspin double` and `keys <presses> lines <n>`.
+
+use ggez::{Context, GameResult, GameError};
+use ggez::filesystem;
+use std::io::Read;
+
+use crate::board::Cell;
+use crate::board_setup::{BoardSetup, parse_preset};
+use crate::tetromino::Kind;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PuzzleGoal {
+	Lines(u16),
+	PerfectClear,
+	TSpinDouble,
+	Keys { presses: u16, lines: u16 }, // clear the lines without pressing more keys than this
+}
+
+impl PuzzleGoal {
+	pub fn parse(text: &str) -> Option<PuzzleGoal> {
+		let words: Vec<&str> = text.split_whitespace().collect();
+		match words.as_slice() {
+			["lines", lines] => lines.parse().ok().map(PuzzleGoal::Lines),
+			["perfect", "clear"] => Some(PuzzleGoal::PerfectClear),
+			["tspin", "double"] => Some(PuzzleGoal::TSpinDouble),
+			["keys", presses, "lines", lines] => Some(PuzzleGoal::Keys { presses: presses.parse().ok()?, lines: lines.parse().ok()? }),
+			_ => None,
+		}
+	}
+
+	pub fn describe(&self) -> String {
+		match self {
+			PuzzleGoal::Lines(lines) => format!("Clear {} lines", lines),
+			PuzzleGoal::PerfectClear => String::from("Perfect clear"),
+			PuzzleGoal::TSpinDouble => String::from("T-spin double"),
+			PuzzleGoal::Keys { presses, lines } => format!("Clear {} lines in {} keys", lines, presses),
+		}
+	}
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Puzzle {
+	pub name: String,
+	pub pieces: Vec<Kind>,
+	pub goal: PuzzleGoal,
+	pub rows: Vec<Vec<Cell>>,
+}
+
+impl Puzzle {
+	pub fn setup(&self) -> BoardSetup {
+		BoardSetup::Preset(self.name.clone(), self.rows.clone())
+	}
+}
+
+pub fn load_pack(ctx: &mut Context, name: &str) -> GameResult<Vec<Puzzle>> {
+	let mut file = filesystem::open(ctx, format!("/puzzles/{}.txt", name))?;
+	let mut text = String::new();
+	file.read_to_string(&mut text)?;
+	parse_pack(&text).map_err(|e| GameError::ResourceLoadError(format!("puzzle pack {}: {}", name, e)))
+}
+
+pub fn parse_pack(text: &str) -> Result<Vec<Puzzle>, String> {
+	let mut puzzles = Vec::new();
+	let mut current: Option<Draft> = None;
+	for line in text.lines() {
+		let line = line.trim_end();
+		if line.is_empty() || line.starts_with('#') {
+			continue;
+		}
+		let (key, value) = match line.find('=') {
+			Some(i) => (line[..i].trim(), line[i + 1..].trim()),
+			None => {
+				let draft = current.as_mut().ok_or_else(|| format!("row before the first name: {}", line))?;
+				draft.rows.push_str(line);
+				draft.rows.push('\n');
+				continue;
+			},
+		};
+		match (key, current.as_mut()) {
+			("name", _) => {
+				if let Some(draft) = current.take() {
+					puzzles.push(draft.finish()?);
+				}
+				current = Some(Draft { name: String::from(value), pieces: None, goal: None, rows: String::new() });
+			},
+			("pieces", Some(draft)) => {
+				let kinds: Option<Vec<Kind>> = value.chars().map(Kind::from_char).collect();
+				draft.pieces = Some(kinds.filter(|kinds| !kinds.is_empty()).ok_or_else(|| format!("bad pieces: {}", value))?);
+			},
+			("goal", Some(draft)) => draft.goal = Some(PuzzleGoal::parse(value).ok_or_else(|| format!("bad goal: {}", value))?),
+			_ => return Err(format!("bad line: {}", line)),
+		}
+	}
+	if let Some(draft) = current {
+		puzzles.push(draft.finish()?);
+	}
+	if puzzles.is_empty() {
+		return Err(String::from("no puzzles"))
+	}
+	Ok(puzzles)
+}
+
+// A puzzle while its lines are being read
+struct Draft {
+	name: String,
+	pieces: Option<Vec<Kind>>,
+	goal: Option<PuzzleGoal>,
+	rows: String,
+}
+
+impl Draft {
+	fn finish(self) -> Result<Puzzle, String> {
+		let Draft { name, pieces, goal, rows } = self;
+		let pieces = pieces.ok_or_else(|| format!("{} has no pieces", name))?;
+		let goal = goal.ok_or_else(|| format!("{} has no goal", name))?;
+		Ok(Puzzle { name, pieces, goal, rows: parse_preset(&rows) })
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn parses_a_pack() {
+		let text = "# two puzzles\nname = One\npieces = TI\ngoal = tspin double\nXX..\nX.XX\n\nname = Two\npieces = O\ngoal = keys 3 lines 1\n";
+		let pack = parse_pack(text).unwrap();
+		assert_eq!(pack.len(), 2);
+		assert_eq!((pack[0].name.as_str(), pack[0].pieces.clone(), pack[0].goal), ("One", vec![Kind::T, Kind::I], PuzzleGoal::TSpinDouble));
+		assert_eq!(pack[0].rows[1], vec![Cell::Garbage, Cell::Empty, Cell::Garbage, Cell::Garbage]);
+		assert_eq!((pack[1].goal, pack[1].rows.len()), (PuzzleGoal::Keys { presses: 3, lines: 1 }, 0));
+	}
+
+	#[test]
+	fn bundled_pack_parses() {
+		let pack = parse_pack(include_str!("../resources/puzzles/basics.txt")).unwrap();
+		assert!(pack.iter().all(|puzzle| puzzle.rows.iter().all(|row| row.len() == 10)));
+	}
+
+	#[test]
+	fn rejects_incomplete_puzzles() {
+		assert!(parse_pack("name = One\ngoal = lines 2\n").is_err());
+		assert!(parse_pack("name = One\npieces = TX\ngoal = lines 2\n").is_err());
+		assert!(parse_pack("XX..\n").is_err());
+		assert!(parse_pack("").is_err());
+	}
+}
@@ -0,0 +1,79 @@
+// Screen for picking a puzzle from the loaded pack
+
+use ggez::{Context, GameResult};
+use ggez::graphics;
+use ggez::event::KeyCode;
+use ggez::nalgebra as na;
+
+use crate::puzzle::Puzzle;
+
+pub struct PuzzleSelectScreen {
+	pub puzzles: Vec<Puzzle>,
+	pub selected: usize,
+	pub solved: Vec<Option<bool>>, // result of the last try at each puzzle
+	font: graphics::Font,
+}
+
+impl PuzzleSelectScreen {
+	pub fn new(ctx: &mut Context, puzzles: Vec<Puzzle>) -> Self {
+		PuzzleSelectScreen {
+			solved: vec![None; puzzles.len()],
+			puzzles,
+			selected: 0,
+			font: graphics::Font::new(ctx, "/arial.ttf").unwrap(),
+		}
+	}
+
+	pub fn current(&self) -> &Puzzle {
+		&self.puzzles[self.selected]
+	}
+
+	// Records how the try at the selected puzzle went, a solved one moves the selection on
+	pub fn finish(&mut self, solved: bool) {
+		self.solved[self.selected] = Some(solved);
+		if solved {
+			self.selected = (self.selected + 1) % self.puzzles.len();
+		}
+	}
+
+	// Returns true when the selected puzzle should be started
+	pub fn key_down(&mut self, key: KeyCode) -> bool {
+		let count = self.puzzles.len();
+		match key {
+			KeyCode::Up => self.selected = (self.selected + count - 1) % count,
+			KeyCode::Down => self.selected = (self.selected + 1) % count,
+			KeyCode::Return => return true,
+			_ => (),
+		}
+		false
+	}
+
+	pub fn draw(&self, ctx: &mut Context, pos: na::Point2<f32>) -> GameResult {
+		self.draw_line(ctx, "Pick a puzzle, Enter to play", pos, graphics::Color::from_rgb(200, 200, 200))?;
+		for (i, puzzle) in self.puzzles.iter().enumerate() {
+			let color = if i == self.selected {
+				graphics::Color::from_rgb(240, 220, 0)
+			} else {
+				graphics::Color::from_rgb(200, 200, 200)
+			};
+			let mark = match self.solved[i] {
+				Some(true) => "solved",
+				Some(false) => "failed",
+				None => "",
+			};
+			let text = format!("{}. {} - {} {}", i + 1, puzzle.name, puzzle.goal.describe(), mark);
+			self.draw_line(ctx, &text, na::Point2::new(pos.x, pos.y + 40.0 + i as f32 * 26.0), color)?;
+		}
+		Ok(())
+	}
+
+	fn draw_line(&self, ctx: &mut Context, text: &str, pos: na::Point2<f32>, color: graphics::Color) -> GameResult {
+		let text = graphics::Text::new(graphics::TextFragment{
+			text: String::from(text),
+			color: Some(color),
+			font: Some(self.font),
+			scale: Some(graphics::Scale::uniform(20.0))
+		});
+		graphics::draw(ctx, &text, (pos,))
+	}
+}
@@ -0,0 +1,120 @@
+// Personal bests stored in the user data directory
+
+use ggez::{Context, GameResult, GameError};
+use ggez::filesystem;
+use std::fs;
+use std::path::PathBuf;
+
+const RECORDS_FILE: &str = "records.cfg";
+
+#[derive(Clone, Debug)]
+pub struct SprintRecord {
+	pub lines: u16,
+	pub ticks: u64,
+	pub splits: Vec<u64>,
+}
+
+pub struct Records {
+	pub sprint: Vec<SprintRecord>,
+}
+
+impl Records {
+	pub fn new() -> Self {
+		Records {
+			sprint: Vec::new(),
+		}
+	}
+
+	fn path(ctx: &Context) -> PathBuf {
+		filesystem::user_data_dir(ctx).join(RECORDS_FILE)
+	}
+
+	pub fn load(ctx: &Context) -> GameResult<Records> {
+		match fs::read_to_string(Self::path(ctx)) {
+			Ok(text) => Records::parse(&text),
+			Err(_) => Ok(Records::new()),
+		}
+	}
+
+	pub fn save(&self, ctx: &Context) -> GameResult {
+		let path = Self::path(ctx);
+		if let Some(dir) = path.parent() {
+			fs::create_dir_all(dir)?;
+		}
+		fs::write(path, self.to_text())?;
+		Ok(())
+	}
+
+	pub fn sprint(&self, lines: u16) -> Option<&SprintRecord> {
+		self.sprint.iter().find(|record| record.lines == lines)
+	}
+
+	// Keeps the run if it beats the stored one, returns true if it did
+	pub fn submit_sprint(&mut self, record: SprintRecord) -> bool {
+		match self.sprint.iter_mut().find(|best| best.lines == record.lines) {
+			Some(best) if best.ticks <= record.ticks => false,
+			Some(best) => {
+				*best = record;
+				true
+			},
+			None => {
+				self.sprint.push(record);
+				true
+			},
+		}
+	}
+
+	// One section per mode and goal, times in ticks:
+	//
+	// [sprint 40]
+	// time = 2612
+	// splits = 610 1290 1975 2612
+	pub fn parse(text: &str) -> GameResult<Records> {
+		let mut records = Records::new();
+		for line in text.lines() {
+			let line = line.trim();
+			if line.is_empty() || line.starts_with('#') {
+				continue;
+			}
+			if line.starts_with('[') && line.ends_with(']') {
+				let mut words = line[1..line.len() - 1].split_whitespace();
+				match (words.next(), words.next().and_then(|goal| goal.parse().ok())) {
+					(Some("sprint"), Some(lines)) => records.sprint.push(SprintRecord { lines, ticks: 0, splits: Vec::new() }),
+					_ => return Err(records_error(line)),
+				}
+				continue;
+			}
+			let mut parts = line.splitn(2, '=');
+			let name = parts.next().unwrap_or("").trim();
+			let value = parts.next().unwrap_or("").trim();
+			let record = records.sprint.last_mut().ok_or_else(|| records_error(line))?;
+			match name {
+				"time" => record.ticks = value.parse().map_err(|_| records_error(line))?,
+				"splits" => {
+					record.splits = value.split_whitespace().map(|tick| tick.parse()).collect::<Result<_, _>>().map_err(|_| records_error(line))?;
+				},
+				_ => return Err(records_error(line)),
+			}
+		}
+		Ok(records)
+	}
+
+	pub fn to_text(&self) -> String {
+		let mut text = String::new();
+		for record in &self.sprint {
+			let splits: Vec<String> = record.splits.iter().map(|tick| tick.to_string()).collect();
+			text += &format!("[sprint {}]\ntime = {}\nsplits = {}\n\n", record.lines, record.ticks, splits.join(" "));
+		}
+		text
+	}
+}
+
+impl Default for Records {
+	fn default() -> Self {
+		Records::new()
+	}
+}
+
+fn records_error(line: &str) -> GameError {
+	GameError::ConfigError(format!("bad records line: {}", line))
+}
@@ -0,0 +1,468 @@
+// Recorded games: everything needed to start the same game again plus every
+// input with the tick it happened on.
+//
+// File layout, numbers are little endian:
+//   "TRPL", version u8, seed u64,
+//   board width, height, buffer as u16,
+//   handling: delay u16, repeat u16, fallback_keeps_charge u8,
+//   board setup: 0 = empty, 1 = garbage (height u16),
+//                2 = preset (name length u16, name, row count u16, rows of width cells),
+//   mode: 0 = marathon (goal 0 = lines or 1 = level, goal value u16, endless u8, variable u8),
+//         1 = sprint (lines u16), 2 = ultra (seconds u16), 3 = dig (rows u16, messy u8), 4 = survival, 5 = master,
+//         6 = invisible (lines u16, fade u16, outline u8),
+//         7 = puzzle (goal 0 = lines, 1 = perfect clear, 2 = T-spin double or 3 = keys,
+//             presses u16, lines u16, piece count u16, the pieces' indices as u8),
+//         8 = versus (attack table as u8: rows for 0 to 4 lines, T-spins with 0 to 3 lines, perfect clear),
+//   input count u32, then per input the tick delta as a varint and
+//   a byte holding the action index shifted left by one, ored with 1 for a press,
+//   then how the game ended u8: 0 = quit, 1 = topped out, 2 = finished (from version 4).
+// Before version 5 the board sizes, garbage height, preset name length, row
+// count and piece count were u8.
+
+use ggez::{Context, GameResult};
+use ggez::filesystem;
+use std::convert::TryFrom;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::board::{Board, Cell};
+use crate::board_setup::BoardSetup;
+use crate::das::Handling;
+use crate::game::Game;
+use crate::input::GameAction;
+use crate::mode::{Mode, MarathonGoal};
+use crate::puzzle::PuzzleGoal;
+use crate::tetromino::Kind;
+use crate::versus::AttackTable;
+
+const MAGIC: &[u8; 4] = b"TRPL";
+const VERSION: u8 = 5;
+// Versions before 3 were played with other level rules and can't be replayed,
+// version 3 files don't record how the game ended
+const OLDEST_VERSION: u8 = 3;
+
+// How a recorded game ended
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Ending {
+	Quit, // closed or restarted before the game was over
+	ToppedOut,
+	Finished,
+}
+
+impl Ending {
+	const ALL: [Ending; 3] = [Ending::Quit, Ending::ToppedOut, Ending::Finished];
+
+	pub fn name(self) -> &'static str {
+		match self {
+			Ending::Quit => "quit",
+			Ending::ToppedOut => "topped out",
+			Ending::Finished => "finished",
+		}
+	}
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ReplayInput {
+	pub tick: u64, // number of ticks simulated before the input
+	pub action: GameAction,
+	pub pressed: bool,
+}
+
+#[derive(Clone)]
+pub struct Replay {
+	pub seed: u64,
+	pub width: usize,
+	pub height: usize,
+	pub buffer: usize,
+	pub handling: Handling,
+	pub setup: BoardSetup,
+	pub mode: Mode,
+	pub pieces: Vec<Kind>, // fixed piece sequence of a puzzle, empty for random pieces
+	pub inputs: Vec<ReplayInput>,
+	pub ending: Option<Ending>, // None for files that don't record it
+}
+
+impl Replay {
+	pub fn new(seed: u64, board: &Board, handling: Handling, setup: BoardSetup, mode: Mode) -> Self {
+		Replay {
+			seed,
+			width: board.width,
+			height: board.height,
+			buffer: board.buffer,
+			handling,
+			setup,
+			mode,
+			pieces: Vec::new(),
+			inputs: Vec::new(),
+			ending: None,
+		}
+	}
+
+	pub fn record(&mut self, tick: u64, action: GameAction, pressed: bool) {
+		self.inputs.push(ReplayInput { tick, action, pressed });
+	}
+
+	// A fresh game in the state the recording started from
+	pub fn start_game(&self) -> Game {
+		let mut game = Game::new(self.seed, Board::new(self.width, self.height, self.buffer), &self.setup, self.handling, self.mode);
+		game.set_pieces(&self.pieces);
+		game
+	}
+
+	// Writes the replay to a new file in the replays folder of the user data directory
+	pub fn save(&self, ctx: &Context) -> GameResult<PathBuf> {
+		let dir = filesystem::user_data_dir(ctx).join("replays");
+		fs::create_dir_all(&dir)?;
+		let time = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis()).unwrap_or(0);
+		let (path, mut file) = create_new(&dir, &time.to_string())?;
+		self.write(&mut file)?;
+		Ok(path)
+	}
+
+	pub fn load(path: &str) -> io::Result<Replay> {
+		Replay::read(&mut fs::File::open(path)?)
+	}
+
+	pub fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+		w.write_all(MAGIC)?;
+		w.write_all(&[VERSION])?;
+		w.write_all(&self.seed.to_le_bytes())?;
+		for size in [self.width, self.height, self.buffer] {
+			write_u16(w, size)?;
+		}
+		w.write_all(&(self.handling.delay as u16).to_le_bytes())?;
+		w.write_all(&(self.handling.repeat as u16).to_le_bytes())?;
+		w.write_all(&[self.handling.fallback_keeps_charge as u8])?;
+		match &self.setup {
+			BoardSetup::Empty => w.write_all(&[0])?,
+			BoardSetup::Garbage(height) => {
+				w.write_all(&[1])?;
+				write_u16(w, *height)?;
+			},
+			BoardSetup::Preset(name, rows) => {
+				w.write_all(&[2])?;
+				write_u16(w, name.len())?;
+				w.write_all(name.as_bytes())?;
+				write_u16(w, rows.len())?;
+				for row in rows {
+					for x in 0..self.width {
+						w.write_all(&[row.get(x).cloned().unwrap_or(Cell::Empty).code()])?;
+					}
+				}
+			},
+		}
+		match self.mode {
+			Mode::Marathon { goal, endless, variable } => {
+				let (kind, value) = match goal {
+					MarathonGoal::Lines(lines) => (0, lines),
+					MarathonGoal::Level(level) => (1, level),
+				};
+				w.write_all(&[0, kind])?;
+				w.write_all(&value.to_le_bytes())?;
+				w.write_all(&[endless as u8, variable as u8])?;
+			},
+			Mode::Sprint { lines } => {
+				w.write_all(&[1])?;
+				w.write_all(&lines.to_le_bytes())?;
+			},
+			Mode::Ultra { seconds } => {
+				w.write_all(&[2])?;
+				w.write_all(&seconds.to_le_bytes())?;
+			},
+			Mode::Survival => w.write_all(&[4])?,
+			Mode::Master => w.write_all(&[5])?,
+			Mode::Invisible { lines, fade, outline } => {
+				w.write_all(&[6])?;
+				w.write_all(&lines.to_le_bytes())?;
+				w.write_all(&fade.to_le_bytes())?;
+				w.write_all(&[outline as u8])?;
+			},
+			Mode::Puzzle(goal) => {
+				let (kind, presses, lines) = match goal {
+					PuzzleGoal::Lines(lines) => (0, 0, lines),
+					PuzzleGoal::PerfectClear => (1, 0, 0),
+					PuzzleGoal::TSpinDouble => (2, 0, 0),
+					PuzzleGoal::Keys { presses, lines } => (3, presses, lines),
+				};
+				w.write_all(&[7, kind])?;
+				w.write_all(&presses.to_le_bytes())?;
+				w.write_all(&lines.to_le_bytes())?;
+				write_u16(w, self.pieces.len())?;
+				for kind in &self.pieces {
+					w.write_all(&[Kind::ALL.iter().position(|k| k == kind).unwrap() as u8])?;
+				}
+			},
+			Mode::Versus(table) => {
+				w.write_all(&[8])?;
+				for rows in table.lines.iter().chain(table.t_spin.iter()).chain(Some(&table.perfect_clear)) {
+					let rows = u8::try_from(*rows).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "attack too big for a replay"))?;
+					w.write_all(&[rows])?;
+				}
+			},
+			Mode::Dig { rows, messy } => {
+				w.write_all(&[3])?;
+				w.write_all(&rows.to_le_bytes())?;
+				w.write_all(&[messy as u8])?;
+			},
+		}
+		w.write_all(&(self.inputs.len() as u32).to_le_bytes())?;
+		let mut last_tick = 0;
+		for input in &self.inputs {
+			write_varint(w, input.tick - last_tick)?;
+			last_tick = input.tick;
+			let index = GameAction::ALL.iter().position(|a| *a == input.action).unwrap() as u8;
+			w.write_all(&[index << 1 | input.pressed as u8])?;
+		}
+		let ending = self.ending.unwrap_or(Ending::Quit);
+		w.write_all(&[Ending::ALL.iter().position(|e| *e == ending).unwrap() as u8])?;
+		Ok(())
+	}
+
+	pub fn read<R: Read>(r: &mut R) -> io::Result<Replay> {
+		let mut magic = [0; 4];
+		r.read_exact(&mut magic)?;
+		if &magic != MAGIC {
+			return Err(invalid("not a replay file"))
+		}
+		let version = read_u8(r)?;
+		if !(OLDEST_VERSION..=VERSION).contains(&version) {
+			return Err(invalid("unsupported replay version"))
+		}
+		let mut seed = [0; 8];
+		r.read_exact(&mut seed)?;
+		// Sizes and counts that were u8 before version 5
+		let read_size = |r: &mut R| if version >= 5 { read_u16(r).map(usize::from) } else { read_u8(r).map(usize::from) };
+		let width = read_size(r)?;
+		let height = read_size(r)?;
+		let buffer = read_size(r)?;
+		crate::board::check_size(width, height, buffer)?;
+		let handling = Handling {
+			delay: read_u16(r)? as i32,
+			repeat: read_u16(r)? as i32,
+			fallback_keeps_charge: read_u8(r)? != 0,
+		};
+		let setup = match read_u8(r)? {
+			0 => BoardSetup::Empty,
+			1 => BoardSetup::Garbage(read_size(r)?),
+			2 => {
+				let mut name = vec![0; read_size(r)?];
+				r.read_exact(&mut name)?;
+				let mut rows = Vec::new();
+				for _ in 0..read_size(r)? {
+					let mut row = Vec::new();
+					for _ in 0..width {
+						row.push(Cell::from_code(read_u8(r)?).ok_or_else(|| invalid("bad cell"))?);
+					}
+					rows.push(row);
+				}
+				BoardSetup::Preset(String::from_utf8_lossy(&name).into_owned(), rows)
+			},
+			_ => return Err(invalid("bad board setup")),
+		};
+		let mut pieces = Vec::new();
+		let mode = match read_u8(r)? {
+			0 => {
+				let goal = match (read_u8(r)?, read_u16(r)?) {
+					(0, lines) => MarathonGoal::Lines(lines),
+					(1, level) => MarathonGoal::Level(level),
+					_ => return Err(invalid("bad marathon goal")),
+				};
+				Mode::Marathon { goal, endless: read_u8(r)? != 0, variable: read_u8(r)? != 0 }
+			},
+			1 => Mode::Sprint { lines: read_u16(r)? },
+			2 => Mode::Ultra { seconds: read_u16(r)? },
+			3 => Mode::Dig { rows: read_u16(r)?, messy: read_u8(r)? != 0 },
+			4 => Mode::Survival,
+			5 => Mode::Master,
+			6 => Mode::Invisible { lines: read_u16(r)?, fade: read_u16(r)?, outline: read_u8(r)? != 0 },
+			7 => {
+				let goal = match (read_u8(r)?, read_u16(r)?, read_u16(r)?) {
+					(0, _, lines) => PuzzleGoal::Lines(lines),
+					(1, _, _) => PuzzleGoal::PerfectClear,
+					(2, _, _) => PuzzleGoal::TSpinDouble,
+					(3, presses, lines) => PuzzleGoal::Keys { presses, lines },
+					_ => return Err(invalid("bad puzzle goal")),
+				};
+				for _ in 0..read_size(r)? {
+					pieces.push(*Kind::ALL.get(read_u8(r)? as usize).ok_or_else(|| invalid("bad piece"))?);
+				}
+				Mode::Puzzle(goal)
+			},
+			8 => {
+				let mut table = AttackTable::new();
+				for rows in table.lines.iter_mut().chain(table.t_spin.iter_mut()).chain(Some(&mut table.perfect_clear)) {
+					*rows = read_u8(r)? as u32;
+				}
+				Mode::Versus(table)
+			},
+			_ => return Err(invalid("bad mode")),
+		};
+		let mut count = [0; 4];
+		r.read_exact(&mut count)?;
+		let mut inputs = Vec::new();
+		let mut tick = 0;
+		for _ in 0..u32::from_le_bytes(count) {
+			tick += read_varint(r)?;
+			let byte = read_u8(r)?;
+			let action = *GameAction::ALL.get((byte >> 1) as usize).ok_or_else(|| invalid("bad action"))?;
+			inputs.push(ReplayInput { tick, action, pressed: byte & 1 == 1 });
+		}
+		let ending = if version >= 4 {
+			Some(*Ending::ALL.get(read_u8(r)? as usize).ok_or_else(|| invalid("bad ending"))?)
+		} else {
+			None
+		};
+		Ok(Replay {
+			seed: u64::from_le_bytes(seed),
+			width,
+			height,
+			buffer,
+			handling,
+			setup,
+			mode,
+			pieces,
+			inputs,
+			ending,
+		})
+	}
+}
+
+// Opens "<stem>.rpl" in dir, or "<stem>-2.rpl" and so on if a replay saved in
+// the same millisecond took the name, so that no replay is overwritten
+fn create_new(dir: &Path, stem: &str) -> io::Result<(PathBuf, fs::File)> {
+	let mut count = 1;
+	loop {
+		let name = if count == 1 { format!("{}.rpl", stem) } else { format!("{}-{}.rpl", stem, count) };
+		let path = dir.join(name);
+		match fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+			Ok(file) => return Ok((path, file)),
+			Err(e) if e.kind() == io::ErrorKind::AlreadyExists => count += 1,
+			Err(e) => return Err(e),
+		}
+	}
+}
+
+fn invalid(message: &str) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read_u8<R: Read>(r: &mut R) -> io::Result<u8> {
+	let mut byte = [0];
+	r.read_exact(&mut byte)?;
+	Ok(byte[0])
+}
+
+fn read_u16<R: Read>(r: &mut R) -> io::Result<u16> {
+	let mut bytes = [0; 2];
+	r.read_exact(&mut bytes)?;
+	Ok(u16::from_le_bytes(bytes))
+}
+
+fn write_u16<W: Write>(w: &mut W, value: usize) -> io::Result<()> {
+	let value = u16::try_from(value).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "too big for a replay"))?;
+	w.write_all(&value.to_le_bytes())
+}
+
+fn write_varint<W: Write>(w: &mut W, mut value: u64) -> io::Result<()> {
+	loop {
+		let byte = (value & 0x7f) as u8;
+		value >>= 7;
+		if value == 0 {
+			return w.write_all(&[byte])
+		}
+		w.write_all(&[byte | 0x80])?;
+	}
+}
+
+fn read_varint<R: Read>(r: &mut R) -> io::Result<u64> {
+	let mut value = 0;
+	let mut shift = 0;
+	loop {
+		let byte = read_u8(r)?;
+		value |= ((byte & 0x7f) as u64) << shift;
+		if byte & 0x80 == 0 {
+			return Ok(value)
+		}
+		shift += 7;
+		if shift > 63 {
+			return Err(invalid("varint too long"))
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn write_and_read_back() {
+		let board = Board::new(6, 8, 4);
+		let rows = vec![vec![Cell::Garbage, Cell::Empty, Cell::Block(Kind::T), Cell::Empty, Cell::Empty, Cell::Block(Kind::L)]];
+		let mut replay = Replay::new(42, &board, Handling::new(), BoardSetup::Preset(String::from("test"), rows.clone()), Mode::Sprint { lines: 20 });
+		replay.record(0, GameAction::MoveLeft, true);
+		replay.record(3, GameAction::MoveLeft, false);
+		replay.record(1000, GameAction::HardDrop, true);
+		replay.ending = Some(Ending::ToppedOut);
+
+		let mut bytes = Vec::new();
+		replay.write(&mut bytes).unwrap();
+		let read = Replay::read(&mut bytes.as_slice()).unwrap();
+
+		assert_eq!((read.seed, read.width, read.height, read.buffer), (42, 6, 8, 4));
+		assert_eq!(read.handling, Handling::new());
+		assert_eq!(read.inputs, replay.inputs);
+		assert_eq!(read.ending, Some(Ending::ToppedOut));
+		assert_eq!(read.mode, Mode::Sprint { lines: 20 });
+		match read.setup {
+			BoardSetup::Preset(name, read_rows) => assert!(name == "test" && read_rows == rows),
+			_ => panic!("wrong board setup"),
+		}
+	}
+
+	#[test]
+	fn puzzles_keep_their_pieces() {
+		let board = Board::new(10, 20, 20);
+		let mut replay = Replay::new(7, &board, Handling::new(), BoardSetup::Empty, Mode::Puzzle(PuzzleGoal::Keys { presses: 4, lines: 2 }));
+		replay.pieces = vec![Kind::T, Kind::I, Kind::L];
+		let mut bytes = Vec::new();
+		replay.write(&mut bytes).unwrap();
+		let read = Replay::read(&mut bytes.as_slice()).unwrap();
+		assert_eq!((read.mode, read.pieces), (replay.mode, replay.pieces));
+	}
+
+	#[test]
+	fn sizes_over_255_survive() {
+		let board = Board::new(300, 20, 260);
+		let replay = Replay::new(1, &board, Handling::new(), BoardSetup::Garbage(280), Mode::Survival);
+		let mut bytes = Vec::new();
+		replay.write(&mut bytes).unwrap();
+		let read = Replay::read(&mut bytes.as_slice()).unwrap();
+		assert_eq!((read.width, read.height, read.buffer), (300, 20, 260));
+		assert!(matches!(read.setup, BoardSetup::Garbage(280)));
+	}
+
+	#[test]
+	fn version_4_files_still_read() {
+		let mut bytes = b"TRPL\x04".to_vec();
+		bytes.extend_from_slice(&9u64.to_le_bytes());
+		bytes.extend_from_slice(&[10, 20, 20, 14, 0, 3, 0, 1]);
+		bytes.extend_from_slice(&[1, 5, 4]);
+		bytes.extend_from_slice(&0u32.to_le_bytes());
+		bytes.push(1);
+		let read = Replay::read(&mut bytes.as_slice()).unwrap();
+		assert_eq!((read.seed, read.width, read.height, read.buffer, read.ending), (9, 10, 20, 20, Some(Ending::ToppedOut)));
+		assert!(matches!(read.setup, BoardSetup::Garbage(5)));
+	}
+
+	#[test]
+	fn replays_saved_at_once_get_names_of_their_own() {
+		let dir = std::env::temp_dir().join(format!("tetris-replays-{}", std::process::id()));
+		fs::create_dir_all(&dir).unwrap();
+		let (first, _) = create_new(&dir, "1000").unwrap();
+		let (second, _) = create_new(&dir, "1000").unwrap();
+		fs::remove_dir_all(&dir).unwrap();
+		assert_eq!((first.file_name().unwrap(), second.file_name().unwrap()), ("1000.rpl".as_ref(), "1000-2.rpl".as_ref()));
+	}
+}
@@ -0,0 +1,180 @@
+// Plays a recorded game through the engine in place of live input
+
+use ggez::event::KeyCode;
+
+use crate::game::{Game, TICKS_PER_SECOND};
+use crate::input::GameAction;
+use crate::replay::Replay;
+
+pub const SPEEDS: [f32; 6] = [0.25, 0.5, 1.0, 2.0, 4.0, 8.0];
+const SEEK_TICKS: u64 = 5 * TICKS_PER_SECOND as u64;
+
+pub struct ReplayViewer {
+	pub replay: Replay,
+	pub paused: bool,
+	pub speed: usize, // index into SPEEDS
+	pub held: Vec<GameAction>, // recorded actions that are down at the current tick
+	next_input: usize,
+	ticks_owed: f32, // part of a tick carried over to the next frame below 1x
+}
+
+impl ReplayViewer {
+	pub fn new(replay: Replay) -> Self {
+		ReplayViewer {
+			replay,
+			paused: false,
+			speed: 2,
+			held: Vec::new(),
+			next_input: 0,
+			ticks_owed: 0.0,
+		}
+	}
+
+	// A new game at tick 0 with the recording rewound
+	pub fn start(&mut self) -> Game {
+		self.next_input = 0;
+		self.held.clear();
+		self.ticks_owed = 0.0;
+		let mut game = self.replay.start_game();
+		self.feed(&mut game);
+		game
+	}
+
+	// Simulates one tick and feeds the inputs that came before the next one
+	pub fn step(&mut self, game: &mut Game) {
+		game.tick();
+		self.feed(game);
+	}
+
+	// Inputs are applied between ticks, the same way live input arrives
+	fn feed(&mut self, game: &mut Game) {
+		while let Some(input) = self.replay.inputs.get(self.next_input) {
+			if input.tick > game.tick {
+				break
+			}
+			self.held.retain(|action| *action != input.action);
+			if input.pressed {
+				self.held.push(input.action);
+			}
+			game.action(input.action, input.pressed);
+			self.next_input += 1;
+		}
+	}
+
+	// Called once per 60 Hz frame, runs as many ticks as the speed asks for
+	pub fn frame(&mut self, game: &mut Game) {
+		if self.paused || game.over {
+			return
+		}
+		self.ticks_owed += SPEEDS[self.speed];
+		while self.ticks_owed >= 1.0 && !game.over {
+			self.ticks_owed -= 1.0;
+			self.step(game);
+		}
+	}
+
+	// Games can't be run backwards, so seeking starts over and fast-forwards to the tick
+	pub fn seek(&mut self, game: &mut Game, tick: u64) {
+		if tick < game.tick {
+			*game = self.start();
+		}
+		while game.tick < tick && !game.over {
+			self.step(game);
+		}
+	}
+
+	pub fn key_down(&mut self, key: KeyCode, game: &mut Game) {
+		match key {
+			KeyCode::Space => self.paused = !self.paused,
+			KeyCode::Up => self.speed = (self.speed + 1).min(SPEEDS.len() - 1),
+			KeyCode::Down => self.speed = self.speed.saturating_sub(1),
+			KeyCode::Period => {
+				self.paused = true;
+				if !game.over {
+					self.step(game);
+				}
+			},
+			KeyCode::Left => self.seek(game, game.tick.saturating_sub(SEEK_TICKS)),
+			KeyCode::Right => self.seek(game, game.tick + SEEK_TICKS),
+			KeyCode::Home => self.seek(game, 0),
+			_ => (),
+		}
+	}
+
+	// Speed, time and held inputs, one line each
+	pub fn overlay(&self, game: &Game) -> Vec<String> {
+		let seconds = game.tick as f32 / TICKS_PER_SECOND as f32;
+		let mut lines = vec![
+			format!("Replay {}x", SPEEDS[self.speed]),
+			format!("{}:{:04.1}", (seconds / 60.0) as u32, seconds % 60.0),
+		];
+		lines.extend(self.held.iter().map(|action| String::from(action.label())));
+		lines
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::board::{Board, Cell};
+	use crate::board_setup::BoardSetup;
+	use crate::das::Handling;
+	use crate::mode::Mode;
+
+	fn cells(game: &Game) -> Vec<Cell> {
+		let mut cells = Vec::new();
+		for y in 0..game.board.rows() {
+			for x in 0..game.board.width {
+				cells.push(game.board.get(x, y));
+			}
+		}
+		cells
+	}
+
+	// Plays a few pieces live the way MainState does and records them
+	fn record() -> (Replay, Game) {
+		let board = Board::new(10, 20, 20);
+		let mut replay = Replay::new(7, &board, Handling::new(), BoardSetup::Garbage(4), Mode::marathon());
+		let mut game = replay.start_game();
+		let inputs = [GameAction::MoveLeft, GameAction::RotateCW, GameAction::MoveRight, GameAction::SoftDrop, GameAction::Hold, GameAction::HardDrop];
+		for i in 0..600 {
+			if i % 7 == 0 {
+				let action = inputs[i / 7 % inputs.len()];
+				replay.record(game.tick, action, true);
+				game.action(action, true);
+			}
+			if i % 7 == 3 {
+				let action = inputs[i / 7 % inputs.len()];
+				replay.record(game.tick, action, false);
+				game.action(action, false);
+			}
+			game.tick();
+		}
+		(replay, game)
+	}
+
+	#[test]
+	fn playback_matches_recorded_game() {
+		let (replay, live) = record();
+		let mut viewer = ReplayViewer::new(replay);
+		let mut game = viewer.start();
+		while game.tick < live.tick {
+			viewer.step(&mut game);
+		}
+		assert_eq!(cells(&game), cells(&live));
+		assert_eq!((game.pieces, game.lines, game.tetr.pos, game.hold), (live.pieces, live.lines, live.tetr.pos, live.hold));
+	}
+
+	#[test]
+	fn seeking_back_resimulates() {
+		let (replay, live) = record();
+		let mut viewer = ReplayViewer::new(replay);
+		let mut game = viewer.start();
+		viewer.seek(&mut game, live.tick);
+		viewer.seek(&mut game, 100);
+		assert_eq!(game.tick, 100);
+		viewer.seek(&mut game, live.tick);
+		assert_eq!(cells(&game), cells(&live));
+		assert!(viewer.held.is_empty());
+	}
+}
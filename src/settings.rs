@@ -0,0 +1,226 @@
+// Player settings stored in the user config directory
+
+use ggez::{Context, GameResult, GameError};
+use ggez::filesystem;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::das::Handling;
+use crate::input::{GameAction, KeyBindings, ButtonBindings, key_name, key_from_name, button_name, button_from_name};
+use crate::versus::AttackTable;
+
+const SETTINGS_FILE: &str = "settings.cfg";
+
+pub struct Settings {
+	pub keys: KeyBindings,
+	pub buttons: ButtonBindings,
+	pub deadzone: f32, // how far a stick has to be pushed to count as pressed, 0..1
+	pub handling: Handling,
+	pub versus_keys: [KeyBindings; 2], // a keyboard map for each player in versus, pause and restart keep the ones above
+	pub attack: AttackTable,
+}
+
+impl Settings {
+	pub fn new() -> Self {
+		Settings {
+			keys: KeyBindings::new(),
+			buttons: ButtonBindings::new(),
+			deadzone: 0.5,
+			handling: Handling::new(),
+			versus_keys: [KeyBindings::versus(0), KeyBindings::versus(1)],
+			attack: AttackTable::new(),
+		}
+	}
+
+	fn path(ctx: &Context) -> PathBuf {
+		filesystem::user_config_dir(ctx).join(SETTINGS_FILE)
+	}
+
+	// Falls back to the defaults when there is no settings file yet
+	pub fn load(ctx: &Context) -> GameResult<Settings> {
+		match fs::read_to_string(Self::path(ctx)) {
+			Ok(text) => Settings::parse(&text),
+			Err(_) => Ok(Settings::new()),
+		}
+	}
+
+	pub fn save(&self, ctx: &Context) -> GameResult {
+		let path = Self::path(ctx);
+		if let Some(dir) = path.parent() {
+			fs::create_dir_all(dir)?;
+		}
+		fs::write(path, self.to_text())?;
+		Ok(())
+	}
+
+	// Sections hold "name = value" lines, an action lists its keys or buttons separated by spaces:
+	//
+	// [keyboard]
+	// rotate_cw = S Up
+	//
+	// [gamepad]
+	// deadzone = 0.5
+	// rotate_cw = East North
+	// hold =
+	//
+	// [handling]
+	// das = 14
+	// arr = 3
+	//
+	// [versus_keyboard_1] and [versus_keyboard_2] are laid out like [keyboard]
+	//
+	// [versus]
+	// lines = 0 0 1 2 4
+	// t_spin = 0 2 4 6
+	// perfect_clear = 10
+	pub fn parse(text: &str) -> GameResult<Settings> {
+		let mut settings = Settings::new();
+		let mut section = String::new();
+		let mut keys = KeyBindings::empty();
+		let mut buttons = ButtonBindings::empty();
+		let mut versus_keys = [KeyBindings::empty(), KeyBindings::empty()];
+		// Actions named in each section, in the order keyboard, gamepad and the two versus maps
+		let mut listed: [Vec<GameAction>; 4] = Default::default();
+		for line in text.lines() {
+			let line = line.trim();
+			if line.is_empty() || line.starts_with('#') {
+				continue;
+			}
+			if line.starts_with('[') && line.ends_with(']') {
+				section = String::from(&line[1..line.len() - 1]);
+				continue;
+			}
+			let mut parts = line.splitn(2, '=');
+			let name = parts.next().unwrap_or("").trim();
+			let value = parts.next().unwrap_or("").trim();
+			match section.as_str() {
+				"keyboard" | "versus_keyboard_1" | "versus_keyboard_2" => {
+					let (bindings, listed) = match section.as_str() {
+						"keyboard" => (&mut keys, &mut listed[0]),
+						"versus_keyboard_1" => (&mut versus_keys[0], &mut listed[2]),
+						_ => (&mut versus_keys[1], &mut listed[3]),
+					};
+					let action = GameAction::from_name(name).ok_or_else(|| settings_error(line))?;
+					listed.push(action);
+					for key in value.split_whitespace() {
+						bindings.add(key_from_name(key).ok_or_else(|| settings_error(line))?, action);
+					}
+				},
+				"gamepad" if name == "deadzone" => {
+					settings.deadzone = value.parse().map_err(|_| settings_error(line))?;
+				},
+				"gamepad" => {
+					let action = GameAction::from_name(name).ok_or_else(|| settings_error(line))?;
+					listed[1].push(action);
+					for button in value.split_whitespace() {
+						buttons.add(button_from_name(button).ok_or_else(|| settings_error(line))?, action);
+					}
+				},
+				"handling" => {
+					match name {
+						"das" => settings.handling.delay = value.parse().map_err(|_| settings_error(line))?,
+						"arr" => settings.handling.repeat = value.parse().map_err(|_| settings_error(line))?,
+						"fallback_keeps_charge" => settings.handling.fallback_keeps_charge = value.parse().map_err(|_| settings_error(line))?,
+						_ => return Err(settings_error(line)),
+					}
+				},
+				"versus" => {
+					let parsed = match name {
+						"lines" => parse_rows(value, &mut settings.attack.lines),
+						"t_spin" => parse_rows(value, &mut settings.attack.t_spin),
+						"perfect_clear" => parse_rows(value, std::slice::from_mut(&mut settings.attack.perfect_clear)),
+						_ => None,
+					};
+					parsed.ok_or_else(|| settings_error(line))?;
+				},
+				_ => return Err(settings_error(line)),
+			}
+		}
+		// Actions missing from the file keep their defaults, "action =" unbinds one
+		keys.fill_missing(&settings.keys, &listed[0]);
+		buttons.fill_missing(&settings.buttons, &listed[1]);
+		settings.keys = keys;
+		settings.buttons = buttons;
+		for ((bindings, defaults), listed) in versus_keys.iter_mut().zip(settings.versus_keys.iter()).zip(listed[2..].iter()) {
+			bindings.fill_missing(defaults, listed);
+		}
+		settings.versus_keys = versus_keys;
+		Ok(settings)
+	}
+
+	pub fn to_text(&self) -> String {
+		let mut text = keyboard_text("keyboard", &self.keys);
+		text += &format!("\n[gamepad]\ndeadzone = {}\n", self.deadzone);
+		for action in GameAction::ALL.iter() {
+			let buttons: Vec<String> = self.buttons.inputs(*action).into_iter().map(button_name).collect();
+			text += &format!("{} = {}\n", action.name(), buttons.join(" "));
+		}
+		text += &format!("\n[handling]\ndas = {}\narr = {}\nfallback_keeps_charge = {}\n",
+			self.handling.delay, self.handling.repeat, self.handling.fallback_keeps_charge);
+		text += &format!("\n{}\n{}", keyboard_text("versus_keyboard_1", &self.versus_keys[0]), keyboard_text("versus_keyboard_2", &self.versus_keys[1]));
+		let rows = |rows: &[u32]| rows.iter().map(|r| r.to_string()).collect::<Vec<String>>().join(" ");
+		text += &format!("\n[versus]\nlines = {}\nt_spin = {}\nperfect_clear = {}\n",
+			rows(&self.attack.lines), rows(&self.attack.t_spin), self.attack.perfect_clear);
+		text
+	}
+}
+
+impl Default for Settings {
+	fn default() -> Self {
+		Settings::new()
+	}
+}
+
+// Actions without keys are left out, versus maps have no pause or restart
+fn keyboard_text(section: &str, keys: &KeyBindings) -> String {
+	let mut text = format!("[{}]\n", section);
+	for action in GameAction::ALL.iter() {
+		let keys: Vec<String> = keys.inputs(*action).into_iter().map(key_name).collect();
+		text += &format!("{} = {}\n", action.name(), keys.join(" "));
+	}
+	text
+}
+
+// Fills `rows` from a list of numbers, which has to be exactly as long
+fn parse_rows(value: &str, rows: &mut [u32]) -> Option<()> {
+	let values: Vec<u32> = value.split_whitespace().map(|v| v.parse().ok()).collect::<Option<_>>()?;
+	if values.len() != rows.len() {
+		return None
+	}
+	rows.copy_from_slice(&values);
+	Some(())
+}
+
+fn settings_error(line: &str) -> GameError {
+	GameError::ConfigError(format!("bad settings line: {}", line))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn unbound_buttons_stay_unbound() {
+		let mut settings = Settings::new();
+		// Binding the hold buttons to pause leaves hold without any
+		for button in settings.buttons.inputs(GameAction::Hold) {
+			settings.buttons.add(button, GameAction::Pause);
+		}
+		let loaded = Settings::parse(&settings.to_text()).unwrap();
+		assert!(loaded.buttons.inputs(GameAction::Hold).is_empty());
+		// Actions the file doesn't mention still get their defaults
+		let loaded = Settings::parse("[gamepad]\ndeadzone = 0.3\n").unwrap();
+		assert_eq!(loaded.buttons.inputs(GameAction::Hold), Settings::new().buttons.inputs(GameAction::Hold));
+	}
+
+	#[test]
+	fn unbound_keys_stay_unbound() {
+		let mut settings = Settings::new();
+		for key in settings.keys.inputs(GameAction::Hold) {
+			settings.keys.add(key, GameAction::Pause);
+		}
+		let loaded = Settings::parse(&settings.to_text()).unwrap();
+		assert!(loaded.keys.inputs(GameAction::Hold).is_empty());
+		assert_eq!(loaded.keys.inputs(GameAction::Pause), settings.keys.inputs(GameAction::Pause));
+	}
+}
@@ -0,0 +1,300 @@
+// Spectators: read-only clients of a hosted online match. A spectator plays
+// the match itself, the same way both players do: the host sends both players'
+// replays without inputs, then the inputs of both sides for every frame played.
+// Frames are played SPECTATOR_DELAY ticks after they arrive, which hides uneven
+// arrival. Someone joining late gets every frame played so far and catches up
+// at once, then checks the boards it arrives at against a snapshot of the
+// host's boards taken when it joined.
+
+use std::collections::VecDeque;
+use std::io;
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+use crate::board::Board;
+use crate::game::Game;
+use crate::net::{self, Connection, FrameInputs, Message, COUNTDOWN, PROTOCOL_VERSION};
+use crate::replay::Replay;
+
+// Ticks a spectator stays behind the newest frame
+pub const SPECTATOR_DELAY: u64 = 30;
+
+// Frames per message when a late spectator catches up
+const FRAMES_PER_MESSAGE: usize = 600;
+
+// The host's side: lets spectators in while the match is on and sends them every frame
+pub struct Broadcast {
+	listener: TcpListener,
+	replays: [Replay; 2],
+	joining: Vec<Connection>, // connected, their watch message hasn't come yet
+	watchers: Vec<Connection>,
+	history: Vec<FrameInputs>, // every frame played so far, for spectators joining late
+}
+
+impl Broadcast {
+	pub fn new(listener: TcpListener, replays: [Replay; 2]) -> io::Result<Broadcast> {
+		listener.set_nonblocking(true)?;
+		Ok(Broadcast {
+			listener,
+			replays,
+			joining: Vec::new(),
+			watchers: Vec::new(),
+			history: Vec::new(),
+		})
+	}
+
+	pub fn watchers(&self) -> usize {
+		self.watchers.len()
+	}
+
+	// Called every tick with the frames the match played and the games as they are after them
+	pub fn frames(&mut self, played: Vec<FrameInputs>, first: &Game, second: &Game) {
+		if !played.is_empty() {
+			let message = Message::Frames { frame: self.history.len() as u64, frames: played };
+			// Spectators that can't be written to have gone
+			self.watchers.retain_mut(|watcher| watcher.send(&message).is_ok());
+			if let Message::Frames { frames, .. } = message {
+				self.history.extend(frames);
+			}
+		} else {
+			self.watchers.retain_mut(|watcher| watcher.flush().is_ok());
+		}
+		let admitted = self.admit();
+		if admitted.is_empty() {
+			return
+		}
+		// The snapshot only checks that late spectators caught up right, they
+		// go without one when the boards are too big for a message
+		let snapshot = Message::Snapshot { frame: self.history.len() as u64, boards: vec![first.board.clone(), second.board.clone()] };
+		let snapshot = if snapshot.fits() { Some(snapshot) } else { None };
+		for mut watcher in admitted {
+			if self.catch_up(&mut watcher, snapshot.as_ref()).is_ok() {
+				self.watchers.push(watcher);
+			}
+		}
+	}
+
+	fn catch_up(&self, watcher: &mut Connection, snapshot: Option<&Message>) -> io::Result<()> {
+		watcher.send(&Message::Match { version: PROTOCOL_VERSION, replays: self.replays.clone() })?;
+		for (i, frames) in self.history.chunks(FRAMES_PER_MESSAGE).enumerate() {
+			watcher.send(&Message::Frames { frame: (i * FRAMES_PER_MESSAGE) as u64, frames: frames.to_vec() })?;
+		}
+		if let Some(snapshot) = snapshot {
+			watcher.send(snapshot)?;
+		}
+		Ok(())
+	}
+
+	// Accepts new connections and returns those that asked to watch
+	fn admit(&mut self) -> Vec<Connection> {
+		while let Ok((stream, _)) = self.listener.accept() {
+			if let Ok(connection) = start(stream) {
+				self.joining.push(connection);
+			}
+		}
+		let mut admitted = Vec::new();
+		for mut connection in std::mem::take(&mut self.joining) {
+			match connection.receive() {
+				Ok(Some(Message::Watch { version })) if version == PROTOCOL_VERSION => admitted.push(connection),
+				Ok(Some(Message::Watch { version })) => {
+					let _ = connection.send(&Message::Reject(net::version_mismatch(version)));
+				},
+				Ok(Some(_)) => {
+					let _ = connection.send(&Message::Reject(String::from("the match already has two players")));
+				},
+				Ok(None) => self.joining.push(connection),
+				Err(_) => (),
+			}
+		}
+		admitted
+	}
+}
+
+fn start(stream: TcpStream) -> io::Result<Connection> {
+	let mut connection = Connection::new(stream)?;
+	connection.start()?;
+	Ok(connection)
+}
+
+// A spectator's side of the match
+pub struct Spectator {
+	pub frame: u64, // frames played, the countdown included
+	pub disconnected: Option<String>,
+	connection: Connection,
+	frames: VecDeque<FrameInputs>, // received, waiting out the delay
+	received: u64,
+	waiting: bool, // holding frames back until the delay has built up
+	snapshot: Option<(u64, Vec<Board>)>, // the host's boards to check once that frame is played
+}
+
+// Joins a hosted match as a spectator, returns both players' replays without inputs
+pub fn watch<A: ToSocketAddrs>(address: A) -> io::Result<(Spectator, [Replay; 2])> {
+	let mut connection = Connection::new(TcpStream::connect(address)?)?;
+	connection.send(&Message::Watch { version: PROTOCOL_VERSION })?;
+	match connection.wait()? {
+		Message::Match { version, replays } if version == PROTOCOL_VERSION => {
+			connection.start()?;
+			let spectator = Spectator {
+				frame: 0,
+				disconnected: None,
+				connection,
+				frames: VecDeque::new(),
+				received: 0,
+				waiting: true,
+				snapshot: None,
+			};
+			Ok((spectator, replays))
+		},
+		Message::Match { version, .. } => Err(net::invalid(&format!("the host runs protocol version {}, this build {}", version, PROTOCOL_VERSION))),
+		Message::Reject(reason) => Err(io::Error::new(io::ErrorKind::ConnectionRefused, reason)),
+		_ => Err(net::invalid("expected the match")),
+	}
+}
+
+impl Spectator {
+	pub fn countdown_left(&self) -> u64 {
+		COUNTDOWN.saturating_sub(self.frame)
+	}
+
+	// Called every tick: reads the frames that came in and plays one, keeping
+	// SPECTATOR_DELAY frames back. A backlog of twice that, like a late join,
+	// is played at once. Once the host has gone the frames left play out.
+	pub fn tick(&mut self, first: &mut Game, second: &mut Game) {
+		if self.disconnected.is_none() {
+			if let Err(e) = self.receive() {
+				self.disconnected = Some(e.to_string());
+			}
+		}
+		let backlog = self.frames.len() as u64;
+		if self.disconnected.is_some() || backlog >= SPECTATOR_DELAY {
+			self.waiting = false;
+		} else if backlog == 0 {
+			self.waiting = true;
+		}
+		let count = match backlog {
+			_ if self.waiting => 0,
+			backlog if backlog > 2 * SPECTATOR_DELAY => backlog - SPECTATOR_DELAY,
+			backlog => backlog.min(1),
+		};
+		for _ in 0..count {
+			let inputs = self.frames.pop_front().unwrap();
+			net::play_frame(self.frame, &inputs, first, second);
+			self.frame += 1;
+			self.check(first, second);
+		}
+	}
+
+	fn receive(&mut self) -> io::Result<()> {
+		while let Some(message) = self.connection.receive()? {
+			match message {
+				Message::Frames { frame, frames } if frame == self.received => {
+					self.received += frames.len() as u64;
+					self.frames.extend(frames);
+				},
+				Message::Snapshot { frame, boards } => self.snapshot = Some((frame, boards)),
+				_ => return Err(net::invalid("unexpected message")),
+			}
+		}
+		Ok(())
+	}
+
+	// Compares the boards with the host's once the snapshot's frame is reached
+	fn check(&mut self, first: &Game, second: &Game) {
+		if self.snapshot.as_ref().is_some_and(|(frame, _)| *frame == self.frame) {
+			let (_, boards) = self.snapshot.take().unwrap();
+			let hashes: Vec<u64> = boards.iter().map(Board::hash).collect();
+			if hashes != [first.board.hash(), second.board.hash()] {
+				self.disconnected = Some(String::from("out of sync with the host"));
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::thread;
+	use std::time::Duration;
+	use crate::board_setup::BoardSetup;
+	use crate::das::Handling;
+	use crate::input::GameAction;
+	use crate::mode::Mode;
+	use crate::versus::AttackTable;
+
+	fn replays() -> [Replay; 2] {
+		let replay = Replay::new(3, &Board::new(10, 20, 20), Handling::new(), BoardSetup::Empty, Mode::Versus(AttackTable::new()));
+		[replay.clone(), replay]
+	}
+
+	// Every 20 frames one player hard drops, the other moves and drops
+	fn inputs(frame: u64) -> FrameInputs {
+		match frame % 40 {
+			0 => [vec![(GameAction::HardDrop, true)], Vec::new()],
+			1 => [vec![(GameAction::HardDrop, false)], vec![(GameAction::MoveLeft, true)]],
+			2 => [Vec::new(), vec![(GameAction::MoveLeft, false)]],
+			20 => [Vec::new(), vec![(GameAction::HardDrop, true)]],
+			21 => [Vec::new(), vec![(GameAction::HardDrop, false)]],
+			_ => [Vec::new(), Vec::new()],
+		}
+	}
+
+	// Hosts a match played by `inputs`, lets a spectator join after `joined`
+	// frames and runs the match for another `after`; `tamper` changes the host's
+	// boards while the spectator joins. Returns the spectator once it has played
+	// every frame, with its games and the host's.
+	fn spectate(joined: u64, after: u64, tamper: fn(&mut Game)) -> (Spectator, [Game; 2], [Game; 2]) {
+		let listener = net::listen("127.0.0.1:0").unwrap();
+		let port = listener.local_addr().unwrap().port();
+		let mut broadcast = Broadcast::new(listener, replays()).unwrap();
+		let [mut first, mut second] = replays().map(|replay| replay.start_game());
+		let mut frame = 0;
+		let mut play = |broadcast: &mut Broadcast, first: &mut Game, second: &mut Game| {
+			let played = inputs(frame);
+			net::play_frame(frame, &played, first, second);
+			frame += 1;
+			broadcast.frames(vec![played], first, second);
+		};
+		for _ in 0..joined {
+			play(&mut broadcast, &mut first, &mut second);
+		}
+		tamper(&mut first);
+		let spectator = thread::spawn(move || watch(("127.0.0.1", port)).unwrap());
+		while !spectator.is_finished() {
+			broadcast.frames(Vec::new(), &first, &second);
+			thread::sleep(Duration::from_millis(1));
+		}
+		let (mut spectator, replays) = spectator.join().unwrap();
+		assert_eq!((replays[1].seed, broadcast.watchers()), (3, 1));
+		let mut shown = replays.map(|replay| replay.start_game());
+		for _ in 0..after {
+			play(&mut broadcast, &mut first, &mut second);
+			let [left, right] = &mut shown;
+			spectator.tick(left, right);
+			thread::sleep(Duration::from_millis(1));
+		}
+		// The spectator stays behind until the host goes, then plays the rest
+		assert!(spectator.frame < joined + after);
+		drop(broadcast);
+		while spectator.frame < joined + after && spectator.disconnected.as_deref() != Some("out of sync with the host") {
+			let [left, right] = &mut shown;
+			spectator.tick(left, right);
+			thread::sleep(Duration::from_millis(1));
+		}
+		(spectator, [first, second], shown)
+	}
+
+	#[test]
+	fn late_spectators_catch_up_and_follow_the_match() {
+		let (spectator, host, shown) = spectate(COUNTDOWN + 300, 200, |_| ());
+		assert_ne!(spectator.disconnected.as_deref(), Some("out of sync with the host"));
+		assert!(host[0].pieces > 10 && host[1].pieces > 10);
+		for (host, shown) in host.iter().zip(shown.iter()) {
+			assert_eq!((shown.board.hash(), shown.pieces, shown.score), (host.board.hash(), host.pieces, host.score));
+		}
+	}
+
+	#[test]
+	fn spectators_notice_boards_that_differ_from_the_host() {
+		let (spectator, _, _) = spectate(COUNTDOWN + 100, 100, |game| game.board.set(0, 39, crate::board::Cell::Garbage));
+		assert_eq!(spectator.disconnected.as_deref(), Some("out of sync with the host"));
+	}
+}
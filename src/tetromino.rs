@@ -1,117 +1,209 @@
 use ggez::nalgebra as na;
-use rand::RngCore;
+use rand::Rng;
 
+use crate::board::{Board, Cell};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Kind {
+	I,
+	O,
+	T,
+	S,
+	Z,
+	J,
+	L,
+}
+
+impl Kind {
+	pub const ALL: [Kind; 7] = [Kind::I, Kind::O, Kind::T, Kind::S, Kind::Z, Kind::J, Kind::L];
+
+	pub fn blocks(self) -> [[[i32; crate::tetromino_width]; crate::tetromino_height]; 4] {
+		match self {
+			Kind::I => Tetromino::create_I(),
+			Kind::O => Tetromino::create_O(),
+			Kind::T => Tetromino::create_T(),
+			Kind::S => Tetromino::create_Z1(),
+			Kind::Z => Tetromino::create_Z2(),
+			Kind::J => Tetromino::create_L2(),
+			Kind::L => Tetromino::create_L1(),
+		}
+	}
+
+	pub fn random<R: Rng>(rng: &mut R) -> Kind {
+		Kind::ALL[rng.gen_range(0, Kind::ALL.len())]
+	}
+
+	pub fn from_char(c: char) -> Option<Kind> {
+		match c {
+			'I' => Some(Kind::I),
+			'O' => Some(Kind::O),
+			'T' => Some(Kind::T),
+			'S' => Some(Kind::S),
+			'Z' => Some(Kind::Z),
+			'J' => Some(Kind::J),
+			'L' => Some(Kind::L),
+			_ => None,
+		}
+	}
+}
+
+// Offsets (x, y) tried for a 180 turn from each rotation state, negative y is up
+const KICKS_180: [[(i32, i32); 6]; 4] = [
+	[(0, 0), (0, -1), (1, -1), (-1, -1), (1, 0), (-1, 0)],
+	[(0, 0), (1, 0), (1, -2), (1, -1), (0, -2), (0, -1)],
+	[(0, 0), (0, 1), (-1, 1), (1, 1), (-1, 0), (1, 0)],
+	[(0, 0), (-1, 0), (-1, -2), (-1, -1), (0, -2), (0, -1)],
+];
+
+#[derive(Clone)]
 pub struct Tetromino {
 	pub pos: na::Point2<i32>,
+	pub kind: Kind,
 	pub blocks: [[[i32; crate::tetromino_width]; crate::tetromino_height]; 4],
 	pub rotation: usize,
 }
 
 impl Tetromino {
-	pub fn fall(&mut self, grid: &mut [[i32; crate::grid_rows]; crate::grid_cols]) -> bool { // has fallen?
-		if self.can_fall(grid) {
+	pub fn fall(&mut self, board: &mut Board) -> bool { // has fallen?
+		if self.can_fall(board) {
 			self.pos.y += 1;
 			false
 		} else {
-			self.place_to_grid(grid);
-			self.reset();
+			self.place_to_grid(board);
 			true
 		}
 	}
 
-	pub fn move_tetromino(&mut self, grid: &mut [[i32; crate::grid_rows]; crate::grid_cols], dir: i8) -> bool { // moved?
-		if self.can_move(grid, dir) {
+	// Drops the piece to the floor and places it, returns the number of rows it fell
+	pub fn hard_drop(&mut self, board: &mut Board) -> i32 {
+		let mut rows = 0;
+		while self.can_fall(board) {
+			self.pos.y += 1;
+			rows += 1;
+		}
+		self.place_to_grid(board);
+		rows
+	}
+
+	pub fn move_tetromino(&mut self, board: &mut Board, dir: i8) -> bool { // moved?
+		if self.can_move(board, dir) {
 			self.pos.x += dir as i32;
 			return true
 		}
 		false
 	}
 
-	pub fn rotate(&mut self, grid: &[[i32; crate::grid_rows]; crate::grid_cols], dir: i8) {
+	pub fn rotate(&mut self, board: &Board, dir: i8) -> bool { // rotated?
 		let next_rotation = rotate_value(self.rotation as i8 + dir, 0, 3);
-		if self.can_rotate(grid, next_rotation as usize) {
+		if self.can_rotate(board, next_rotation as usize) {
 			self.rotation = next_rotation as usize;
+			return true
 		}
+		false
 	}
 
-	pub fn new() -> Self {
-		println!("tetromino new");
+	// Turns the piece by two states, trying the offsets of the 180 kick table in order
+	pub fn rotate_180(&mut self, board: &Board) -> bool { // rotated?
+		let next_rotation = rotate_value(self.rotation as i8 + 2, 0, 3) as usize;
+		for (dx, dy) in KICKS_180[self.rotation].iter() {
+			if self.fits(board, next_rotation, self.pos.x + dx, self.pos.y + dy) {
+				self.pos.x += dx;
+				self.pos.y += dy;
+				self.rotation = next_rotation;
+				return true
+			}
+		}
+		false
+	}
+
+	pub fn new(kind: Kind) -> Self {
 		Self {
 			pos: na::Point2::<i32>::new(0,0),
-			blocks: Self::randomized_blocks(),
+			kind,
+			blocks: kind.blocks(),
 			rotation: 0,
 		}
 	}
 
-	pub fn reset(&mut self) {
-		self.blocks = Self::randomized_blocks();
-		self.rotation = 0;
-		self.pos.y = 0;
-		self.pos.x = (crate::grid_cols/2 - 1) as i32;
+	pub fn set_kind(&mut self, kind: Kind) {
+		self.kind = kind;
+		self.blocks = kind.blocks();
 	}
 
-	pub fn randomized_blocks() -> [[[i32; crate::tetromino_width]; crate::tetromino_height]; 4] {
-		let random = rand::thread_rng().next_u32() % 6;
-		match random {
-			0 => Self::create_L1(),
-			1 => Self::create_L2(),
-			2 => Self::create_T(),
-			3 => Self::create_Z1(),
-			4 => Self::create_Z2(),
-			5 => Self::create_I(),
-			x => [[[1; crate::tetromino_width]; crate::tetromino_height]; 4],
-		}
+	// Puts the piece into the buffer so that it occupies rows 21 and 22
+	pub fn spawn(&mut self, board: &Board) {
+		self.rotation = 0;
+		let bottom = (0..crate::tetromino_height).rev()
+			.find(|y| self.blocks[0][*y].contains(&1))
+			.unwrap_or(0);
+		self.pos.y = board.spawn_row() - bottom as i32;
+		self.pos.x = (board.width/2 - 1) as i32;
 	}
 
-	pub fn can_fall(&self, grid: &[[i32; crate::grid_rows]; crate::grid_cols]) -> bool {
+	pub fn fits(&self, board: &Board, rotation: usize, pos_x: i32, pos_y: i32) -> bool {
 		for x in 0..crate::tetromino_width {
 			for y in 0..crate::tetromino_height {
-				if self.blocks[self.rotation][y][x] == 1 {
-					let block_pos = (self.pos.x + x as i32,self.pos.y + y as i32);
-					let below_pos = (block_pos.0, block_pos.1+1);
-					if below_pos.1 >= crate::grid_rows as i32 || grid[below_pos.0 as usize][below_pos.1 as usize] == 1 {
-						return false
-					}
+				if self.blocks[rotation][y][x] == 1 && !board.is_free(pos_x + x as i32, pos_y + y as i32) {
+					return false
 				}
 			}
 		}
 		true
 	}
 
-	pub fn can_move(&self, grid: &[[i32; crate::grid_rows]; crate::grid_cols], dir: i8) -> bool {
+	// Lock out: every block ended up in the hidden buffer
+	pub fn above_visible(&self, board: &Board) -> bool {
 		for x in 0..crate::tetromino_width {
 			for y in 0..crate::tetromino_height {
-				if self.blocks[self.rotation][y][x] == 1 {
-					let check_block_pos = (self.pos.x as i8 + x as i8 + dir,self.pos.y as i8 + y as i8);
-					if check_block_pos.0 < 0 || check_block_pos.0 >= crate::grid_cols as i8 || grid[check_block_pos.0 as usize][check_block_pos.1 as usize] == 1 {
-						return false
-					}
+				if self.blocks[self.rotation][y][x] == 1 && board.is_visible(self.pos.y + y as i32) {
+					return false
 				}
 			}
 		}
 		true
 	}
 
-	pub fn can_rotate(&self, grid: &[[i32; crate::grid_rows]; crate::grid_cols], rotation: usize) -> bool {
-		for x in 0..crate::tetromino_width {
-			for y in 0..crate::tetromino_height {
-				if self.blocks[rotation][y][x] == 1 {
-					let block_pos = (self.pos.x as i8 + x as i8, self.pos.y as i8 + y as i8);
-					if block_pos.0 < 0 || block_pos.0 >= crate::grid_cols as i8 || block_pos.1 >= crate::grid_rows as i8 || 
-						grid[block_pos.0 as usize][block_pos.1 as usize] == 1 {
-						return false
-					}
+	// T-spin corner rule: three of the four cells diagonal to the T's center are blocked
+	pub fn t_corners_blocked(&self, board: &Board) -> bool {
+		if self.kind != Kind::T {
+			return false
+		}
+		let blocks = &self.blocks[self.rotation];
+		let filled = |x: i32, y: i32| {
+			x >= 0 && y >= 0 && (x as usize) < crate::tetromino_width && (y as usize) < crate::tetromino_height && blocks[y as usize][x as usize] == 1
+		};
+		// The center is the block with three neighbours
+		for y in 0..crate::tetromino_height as i32 {
+			for x in 0..crate::tetromino_width as i32 {
+				let neighbours = [(-1, 0), (1, 0), (0, -1), (0, 1)].iter().filter(|(dx, dy)| filled(x + dx, y + dy)).count();
+				if filled(x, y) && neighbours == 3 {
+					let corners = [(-1, -1), (1, -1), (-1, 1), (1, 1)].iter()
+						.filter(|(dx, dy)| !board.is_free(self.pos.x + x + dx, self.pos.y + y + dy))
+						.count();
+					return corners >= 3
 				}
 			}
 		}
-		true
+		false
 	}
 
-	pub fn place_to_grid(&self, grid: &mut [[i32; crate::grid_rows]; crate::grid_cols]) {
+	pub fn can_fall(&self, board: &Board) -> bool {
+		self.fits(board, self.rotation, self.pos.x, self.pos.y + 1)
+	}
+
+	pub fn can_move(&self, board: &Board, dir: i8) -> bool {
+		self.fits(board, self.rotation, self.pos.x + dir as i32, self.pos.y)
+	}
+
+	pub fn can_rotate(&self, board: &Board, rotation: usize) -> bool {
+		self.fits(board, rotation, self.pos.x, self.pos.y)
+	}
+
+	pub fn place_to_grid(&self, board: &mut Board) {
 		for x in 0..crate::tetromino_width {
 			for y in 0..crate::tetromino_height {
 				if self.blocks[self.rotation][y][x] == 1 {
-					grid[(self.pos.x + x as i32) as usize][(self.pos.y + y as i32) as usize] = 1;
+					board.set((self.pos.x + x as i32) as usize, (self.pos.y + y as i32) as usize, Cell::Block(self.kind));
 				}
 			}
 		}
@@ -262,6 +354,35 @@ impl Tetromino {
 		]
 	}
 
+	pub fn create_O() -> [[[i32; crate::tetromino_width]; crate::tetromino_height]; 4] {
+		[
+			[
+				[0,1,1,0],
+				[0,1,1,0],
+				[0,0,0,0],
+				[0,0,0,0],
+			],
+			[
+				[0,1,1,0],
+				[0,1,1,0],
+				[0,0,0,0],
+				[0,0,0,0],
+			],
+			[
+				[0,1,1,0],
+				[0,1,1,0],
+				[0,0,0,0],
+				[0,0,0,0],
+			],
+			[
+				[0,1,1,0],
+				[0,1,1,0],
+				[0,0,0,0],
+				[0,0,0,0],
+			],
+		]
+	}
+
 	pub fn create_I() -> [[[i32; crate::tetromino_width]; crate::tetromino_height]; 4] {
 		[
 			[
@@ -293,11 +414,7 @@ impl Tetromino {
 
 }
 
-pub fn rotate_value<T: PartialOrd>(value: T, min: T, max: T) -> T {
-	if value < min {
-		return max
-	} else if value > max {
-		return min
-	}
-	value
+// Wraps value into min..=max, so a rotation can move by several states at once
+pub fn rotate_value(value: i8, min: i8, max: i8) -> i8 {
+	min + (value - min).rem_euclid(max - min + 1)
 }
\ No newline at end of file
@@ -0,0 +1,290 @@
+// Block skins and color themes
+
+use ggez::{Context, GameResult, GameError};
+use ggez::graphics;
+use ggez::filesystem;
+use ggez::nalgebra as na;
+use std::io::Read;
+
+use crate::board::Cell;
+use crate::tetromino::Kind;
+
+pub enum BlockStyle {
+	Classic,  // filled square with a highlight dot
+	Flat,
+	Beveled,
+	Outlined,
+	Textured, // tiles from the theme's tileset
+}
+
+pub struct Theme {
+	pub name: String,
+	pub style: BlockStyle,
+	pub colors: [graphics::Color; 7], // in Kind::ALL order
+	pub garbage: graphics::Color,
+	pub background: graphics::Color,
+	pub grid_lines: Option<graphics::Color>,
+	pub panel: graphics::Color,
+	pub panel_border: graphics::Color,
+	// Image with 8 square tiles in a row: I O T S Z J L and garbage
+	pub tileset: Option<String>,
+}
+
+impl Theme {
+	// Built-in look with Guideline colors
+	pub fn classic() -> Self {
+		Theme {
+			name: String::from("classic"),
+			style: BlockStyle::Classic,
+			colors: [
+				graphics::Color::from((0, 220, 235)),
+				graphics::Color::from((240, 220, 0)),
+				graphics::Color::from((160, 40, 200)),
+				graphics::Color::from((40, 200, 60)),
+				graphics::Color::from((225, 30, 40)),
+				graphics::Color::from((12, 80, 220)),
+				graphics::Color::from((245, 140, 0)),
+			],
+			garbage: graphics::Color::from((130, 130, 130)),
+			background: graphics::BLACK,
+			grid_lines: None,
+			panel: graphics::BLACK,
+			panel_border: graphics::WHITE,
+			tileset: None,
+		}
+	}
+
+	pub fn load(ctx: &mut Context, name: &str) -> GameResult<Theme> {
+		if name == "classic" {
+			return Ok(Theme::classic())
+		}
+		let mut file = filesystem::open(ctx, format!("/themes/{}.theme", name))?;
+		let mut text = String::new();
+		file.read_to_string(&mut text)?;
+		let mut theme = Theme::parse(&text)?;
+		theme.name = String::from(name);
+		Ok(theme)
+	}
+
+	// Names of the built-in theme and every .theme file in resources/themes/
+	pub fn list(ctx: &mut Context) -> Vec<String> {
+		let mut names = vec![String::from("classic")];
+		if let Ok(entries) = filesystem::read_dir(ctx, "/themes") {
+			let mut files: Vec<String> = entries
+				.filter(|path| path.extension().map(|e| e == "theme").unwrap_or(false))
+				.filter_map(|path| path.file_stem().map(|s| s.to_string_lossy().into_owned()))
+				.collect();
+			files.sort();
+			names.extend(files);
+		}
+		names
+	}
+
+	// "key = value" lines, colors are written as "r g b". Missing keys keep the classic values.
+	pub fn parse(text: &str) -> GameResult<Theme> {
+		let mut theme = Theme::classic();
+		for line in text.lines() {
+			let line = line.trim();
+			if line.is_empty() || line.starts_with('#') {
+				continue;
+			}
+			let mut parts = line.splitn(2, '=');
+			let key = parts.next().unwrap_or("").trim();
+			let value = parts.next().unwrap_or("").trim();
+			match key {
+				"style" => theme.style = match value {
+					"classic" => BlockStyle::Classic,
+					"flat" => BlockStyle::Flat,
+					"beveled" => BlockStyle::Beveled,
+					"outlined" => BlockStyle::Outlined,
+					"textured" => BlockStyle::Textured,
+					_ => return Err(theme_error(line)),
+				},
+				"background" => theme.background = parse_color(value).ok_or_else(|| theme_error(line))?,
+				"grid" => theme.grid_lines = Some(parse_color(value).ok_or_else(|| theme_error(line))?),
+				"panel" => theme.panel = parse_color(value).ok_or_else(|| theme_error(line))?,
+				"panel_border" => theme.panel_border = parse_color(value).ok_or_else(|| theme_error(line))?,
+				"garbage" => theme.garbage = parse_color(value).ok_or_else(|| theme_error(line))?,
+				"tileset" => theme.tileset = Some(String::from(value)),
+				key => {
+					let kind = key.chars().next().filter(|_| key.len() == 1).and_then(Kind::from_char).ok_or_else(|| theme_error(line))?;
+					theme.colors[kind_index(kind)] = parse_color(value).ok_or_else(|| theme_error(line))?;
+				},
+			}
+		}
+		Ok(theme)
+	}
+
+	pub fn kind_color(&self, kind: Kind) -> graphics::Color {
+		self.colors[kind_index(kind)]
+	}
+
+	pub fn cell_color(&self, cell: Cell) -> graphics::Color {
+		match cell {
+			Cell::Block(kind) => self.kind_color(kind),
+			Cell::Garbage => self.garbage,
+			Cell::Empty => self.background,
+		}
+	}
+}
+
+fn kind_index(kind: Kind) -> usize {
+	Kind::ALL.iter().position(|k| *k == kind).unwrap()
+}
+
+fn parse_color(value: &str) -> Option<graphics::Color> {
+	let rgb: Vec<u8> = value.split_whitespace().filter_map(|v| v.parse().ok()).collect();
+	if rgb.len() != 3 {
+		return None
+	}
+	Some(graphics::Color::from((rgb[0], rgb[1], rgb[2])))
+}
+
+fn theme_error(line: &str) -> GameError {
+	GameError::ResourceLoadError(format!("bad theme line: {}", line))
+}
+
+// Meshes of a theme built for a given cell size
+pub struct Skin {
+	pub theme: Theme,
+	cellsize: f32,
+	block_mesh: graphics::Mesh,
+	highlight_mesh: Option<graphics::Mesh>,
+	empty_mesh: graphics::Mesh,
+	tileset: Option<graphics::Image>,
+}
+
+impl Skin {
+	pub fn new(ctx: &mut Context, theme: Theme, cellsize: f32) -> GameResult<Skin> {
+		// Block meshes are white so they can be tinted with the cell color
+		let inner = graphics::Rect{x:2f32, y:2f32, w:cellsize - 2.0, h:cellsize - 2.0};
+		let block_mesh = match theme.style {
+			BlockStyle::Outlined => graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::stroke(2.0), graphics::Rect{x:3f32, y:3f32, w:cellsize - 4.0, h:cellsize - 4.0}, graphics::WHITE)?,
+			BlockStyle::Beveled => {
+				let bevel = (cellsize / 6.0).max(2.0);
+				let (x0, y0, x1, y1) = (inner.x, inner.y, inner.x + inner.w, inner.y + inner.h);
+				graphics::MeshBuilder::new()
+					.rectangle(graphics::DrawMode::fill(), inner, graphics::Color::new(0.8, 0.8, 0.8, 1.0))
+					.polygon(graphics::DrawMode::fill(), &[
+						na::Point2::new(x0, y0), na::Point2::new(x1, y0), na::Point2::new(x1 - bevel, y0 + bevel),
+						na::Point2::new(x0 + bevel, y0 + bevel), na::Point2::new(x0 + bevel, y1 - bevel), na::Point2::new(x0, y1),
+					], graphics::WHITE)?
+					.polygon(graphics::DrawMode::fill(), &[
+						na::Point2::new(x1, y1), na::Point2::new(x0, y1), na::Point2::new(x0 + bevel, y1 - bevel),
+						na::Point2::new(x1 - bevel, y1 - bevel), na::Point2::new(x1 - bevel, y0 + bevel), na::Point2::new(x1, y0),
+					], graphics::Color::new(0.5, 0.5, 0.5, 1.0))?
+					.build(ctx)?
+			},
+			_ => graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::fill(), inner, graphics::WHITE)?,
+		};
+		let highlight_mesh = match theme.style {
+			BlockStyle::Classic => Some(graphics::Mesh::new_circle(
+				ctx,
+				graphics::DrawMode::fill(),
+				graphics::mint::Point2{x:5f32,y:5f32},
+				2f32,
+				1f32,
+				graphics::Color::from((255, 255, 254))
+			)?),
+			_ => None,
+		};
+		let cell_rect = graphics::Rect{x:0., y:0., w:cellsize, h:cellsize};
+		let mut empty_builder = graphics::MeshBuilder::new();
+		empty_builder.rectangle(graphics::DrawMode::fill(), cell_rect, theme.background);
+		if let Some(grid_color) = theme.grid_lines {
+			empty_builder.rectangle(graphics::DrawMode::stroke(1.0), cell_rect, grid_color);
+		}
+		let empty_mesh = empty_builder.build(ctx)?;
+		let tileset = match (&theme.style, &theme.tileset) {
+			(BlockStyle::Textured, Some(path)) => Some(graphics::Image::new(ctx, path)?),
+			(BlockStyle::Textured, None) => return Err(GameError::ResourceLoadError(format!("theme {} has no tileset", theme.name))),
+			_ => None,
+		};
+		Ok(Skin {
+			theme,
+			cellsize,
+			block_mesh,
+			highlight_mesh,
+			empty_mesh,
+			tileset,
+		})
+	}
+
+	pub fn draw_cell(&self, ctx: &mut Context, cell: Cell, pos: na::Point2<f32>) -> GameResult {
+		self.draw_faded(ctx, cell, pos, 1.0)
+	}
+
+	// Draws a block see-through over an empty cell, at alpha 0 the cell looks empty
+	pub fn draw_faded(&self, ctx: &mut Context, cell: Cell, pos: na::Point2<f32>, alpha: f32) -> GameResult {
+		if cell == Cell::Empty || alpha < 1.0 {
+			graphics::draw(ctx, &self.empty_mesh, (pos,))?;
+		}
+		if cell == Cell::Empty || alpha <= 0.0 {
+			return Ok(())
+		}
+		let tint = graphics::Color::new(1.0, 1.0, 1.0, alpha);
+		if let Some(tileset) = &self.tileset {
+			let tile = match cell {
+				Cell::Block(kind) => kind_index(kind),
+				_ => 7,
+			};
+			let scale = self.cellsize / tileset.height() as f32;
+			return graphics::draw(ctx, tileset, graphics::DrawParam::new()
+				.src(graphics::Rect::new(tile as f32 / 8.0, 0.0, 1.0 / 8.0, 1.0))
+				.dest(pos)
+				.color(tint)
+				.scale(na::Vector2::new(scale, scale)))
+		}
+		let mut color = self.theme.cell_color(cell);
+		color.a = alpha;
+		graphics::draw(ctx, &self.block_mesh, (pos, color))?;
+		if let Some(highlight) = &self.highlight_mesh {
+			graphics::draw(ctx, highlight, (pos, tint))?;
+		}
+		Ok(())
+	}
+
+	pub fn draw_block(&self, ctx: &mut Context, kind: Kind, pos: na::Point2<f32>) -> GameResult {
+		self.draw_cell(ctx, Cell::Block(kind), pos)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn textured_theme_names_its_tileset() {
+		let theme = Theme::parse(include_str!("../resources/themes/tiles.theme")).unwrap();
+		assert!(matches!(theme.style, BlockStyle::Textured));
+		assert_eq!(theme.tileset.as_deref(), Some("/themes/tiles.png"));
+		assert_eq!(theme.background, graphics::Color::from((10, 10, 14)));
+	}
+
+	#[test]
+	fn missing_keys_keep_the_classic_values() {
+		let theme = Theme::parse("# just a comment\n\nT = 1 2 3\n").unwrap();
+		assert_eq!(theme.kind_color(Kind::T), graphics::Color::from((1, 2, 3)));
+		assert_eq!(theme.kind_color(Kind::I), Theme::classic().kind_color(Kind::I));
+		assert!(theme.tileset.is_none());
+	}
+
+	#[test]
+	fn malformed_lines_are_rejected() {
+		for text in ["style = shiny", "background = 1 2", "grid = red", "Q = 1 2 3", "just words"].iter() {
+			assert!(Theme::parse(text).is_err(), "{}", text);
+		}
+	}
+
+	#[test]
+	fn shipped_themes_parse() {
+		let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/resources/themes");
+		for entry in std::fs::read_dir(dir).unwrap() {
+			let path = entry.unwrap().path();
+			if path.extension().map(|e| e == "theme").unwrap_or(false) {
+				let text = std::fs::read_to_string(&path).unwrap();
+				assert!(Theme::parse(&text).is_ok(), "{}", path.display());
+			}
+		}
+	}
+}
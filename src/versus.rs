@@ -0,0 +1,135 @@
+// Versus: two games played side by side. Line clears attack the opponent with
+// rows of garbage, which first cancel garbage waiting in the attacker's own
+// meter. Whatever is left waits in the opponent's meter and rises into their
+// board when they place a piece without clearing a line.
+
+use crate::game::Game;
+
+// Rows of garbage sent for a clear
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct AttackTable {
+	pub lines: [u32; 5], // clearing 0 to 4 lines
+	pub t_spin: [u32; 4], // T-spins clearing 0 to 3 lines, instead of the above
+	pub perfect_clear: u32, // added when the clear leaves the board empty
+}
+
+impl AttackTable {
+	pub fn new() -> Self {
+		AttackTable {
+			lines: [0, 0, 1, 2, 4],
+			t_spin: [0, 2, 4, 6],
+			perfect_clear: 10,
+		}
+	}
+
+	pub fn attack(&self, lines: u32, t_spin: bool, perfect_clear: bool) -> u32 {
+		let rows = if t_spin {
+			self.t_spin[(lines as usize).min(self.t_spin.len() - 1)]
+		} else {
+			self.lines[(lines as usize).min(self.lines.len() - 1)]
+		};
+		if perfect_clear {
+			rows + self.perfect_clear
+		} else {
+			rows
+		}
+	}
+}
+
+impl Default for AttackTable {
+	fn default() -> Self {
+		AttackTable::new()
+	}
+}
+
+// Called after the games have ticked or taken an action: moves the attacks of
+// each game into the other one's meter and ends the match once someone tops out.
+pub fn settle(first: &mut Game, second: &mut Game) {
+	let (sent_first, sent_second) = (first.take_attack(), second.take_attack());
+	second.receive(sent_first);
+	first.receive(sent_second);
+	// The first player to top out loses, if both did on the same tick nobody wins
+	if first.over != second.over {
+		let winner = if first.over { second } else { first };
+		winner.finish();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::board::{Board, Cell};
+	use crate::board_setup::BoardSetup;
+	use crate::das::Handling;
+	use crate::input::GameAction;
+	use crate::mode::Mode;
+	use crate::tetromino::Kind;
+
+	fn game() -> Game {
+		with_table(AttackTable::new())
+	}
+
+	fn with_table(table: AttackTable) -> Game {
+		let mut game = Game::new(1, Board::new(10, 20, 20), &BoardSetup::Empty, Handling::new(), Mode::Versus(table));
+		game.set_pieces(&[Kind::I, Kind::I, Kind::I, Kind::I]);
+		game
+	}
+
+	// Fills the bottom rows except for the four columns the I piece spawns over,
+	// with a block on top so that clearing them isn't a perfect clear
+	fn leave_well(game: &mut Game, rows: usize) {
+		let floor = game.board.rows() - 1;
+		for y in floor + 1 - rows..=floor {
+			for x in (0..4).chain(8..10) {
+				game.board.set(x, y, Cell::Garbage);
+			}
+		}
+		game.board.set(0, floor - rows, Cell::Garbage);
+	}
+
+	#[test]
+	fn table_scores_clears() {
+		let table = AttackTable::new();
+		assert_eq!((table.attack(1, false, false), table.attack(4, false, false)), (0, 4));
+		assert_eq!(table.attack(2, true, false), 4);
+		assert_eq!(table.attack(1, false, true), 10);
+	}
+
+	#[test]
+	fn attacks_cancel_the_meter_before_reaching_the_opponent() {
+		let (mut first, mut second) = (game(), game());
+		leave_well(&mut first, 1);
+		first.receive(3);
+		// Clearing a line with an I placed flat sends nothing in the default table
+		first.action(GameAction::HardDrop, true);
+		settle(&mut first, &mut second);
+		assert_eq!((first.garbage_waiting(), second.garbage_waiting()), (3, 0));
+
+		let (mut first, mut second) = (with_table(AttackTable { lines: [0, 5, 5, 5, 5], ..AttackTable::new() }), game());
+		leave_well(&mut first, 1);
+		first.receive(3);
+		first.action(GameAction::HardDrop, true);
+		settle(&mut first, &mut second);
+		assert_eq!((first.garbage_waiting(), second.garbage_waiting()), (0, 2));
+	}
+
+	#[test]
+	fn waiting_garbage_rises_when_a_piece_clears_nothing() {
+		let (mut first, mut second) = (game(), game());
+		second.receive(2);
+		second.receive(1);
+		second.action(GameAction::HardDrop, true);
+		settle(&mut first, &mut second);
+		assert_eq!((second.garbage_waiting(), second.board.garbage_rows()), (0, 3));
+	}
+
+	#[test]
+	fn first_to_top_out_loses() {
+		let (mut first, mut second) = (game(), game());
+		second.receive(40);
+		second.action(GameAction::HardDrop, true);
+		settle(&mut first, &mut second);
+		assert!(second.over && !second.won);
+		assert!(first.over && first.won);
+	}
+}